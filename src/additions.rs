@@ -0,0 +1,65 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::{debug, instrument, warn};
+
+use crate::persist;
+
+/// File messages added with `/beer-add` are kept in, in the data dir.
+const ADDITIONS_FILE: &str = "messages.json";
+
+/// Messages added with `/beer-add`, persisted separately from the config so it's never overwritten.
+#[derive(Debug)]
+pub struct Additions {
+    messages: Mutex<Vec<String>>,
+
+    /// Held while writing, so two `/beer-add`s at once are both written.
+    writing: tokio::sync::Mutex<()>,
+
+    /// Where the messages are persisted, if there's anywhere to.
+    file: Option<PathBuf>,
+}
+
+impl Additions {
+    /// Restore the messages added before the last run.
+    #[instrument]
+    pub async fn load() -> Additions {
+        let file = persist::data_file(ADDITIONS_FILE);
+        let messages = persist::load_json(file.as_deref())
+            .await
+            .unwrap_or_else(|e| {
+                warn!(?e, "failed to read added messages, ignoring them");
+                Vec::new()
+            });
+        debug!(count = messages.len(), ?file, "loaded added messages");
+
+        Additions {
+            messages: Mutex::new(messages),
+            writing: tokio::sync::Mutex::new(()),
+            file,
+        }
+    }
+
+    pub fn messages(&self) -> Vec<String> {
+        self.messages
+            .lock()
+            .expect("Added messages poisoned")
+            .clone()
+    }
+
+    /// Add `text` to the messages, persisting it for the next run.
+    pub async fn add(&self, text: String) -> Result<()> {
+        let _writing = self.writing.lock().await;
+        self.messages
+            .lock()
+            .expect("Added messages poisoned")
+            .push(text);
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        persist::write_json(file, &self.messages()).await?;
+
+        Ok(())
+    }
+}
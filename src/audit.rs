@@ -0,0 +1,62 @@
+#![cfg_attr(not(feature = "audit"), allow(unused_imports))]
+use crate::config::{Config, CronJob};
+use crate::message::HttpClient;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use slack_morphism::{SlackChannelId, SlackMessageContent};
+use tracing::{instrument, trace};
+
+/// Record of an announcement, sent to `audit_webhook`.
+#[cfg(feature = "audit")]
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    channel: &'a SlackChannelId,
+    text: Option<&'a str>,
+    timestamp: DateTime<Utc>,
+
+    /// The cron which sent the announcement, if any.
+    cron: Option<String>,
+}
+
+/// Send a record of an announcement to `audit_webhook`, if it's set.
+#[cfg(feature = "audit")]
+#[instrument(skip_all, fields(%channel))]
+pub async fn record(
+    cfg: &Config,
+    http: &HttpClient,
+    channel: &SlackChannelId,
+    content: &SlackMessageContent,
+    cron: Option<&CronJob>,
+) -> Result<()> {
+    let Some(url) = &cfg.audit_webhook else {
+        return Ok(());
+    };
+
+    let record = AuditRecord {
+        channel,
+        text: content.text.as_deref(),
+        timestamp: Utc::now(),
+        cron: cron.map(|c| c.to_string()),
+    };
+    trace!(?record, "auditing");
+    http.post(url.clone())
+        .json(&record)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "audit"))]
+#[inline]
+pub async fn record(
+    _: &Config,
+    _: &HttpClient,
+    _: &SlackChannelId,
+    _: &SlackMessageContent,
+    _: Option<&CronJob>,
+) -> Result<()> {
+    Ok(())
+}
@@ -1,66 +1,322 @@
 #![cfg_attr(not(feature = "commands"), allow(unused_imports))]
-use crate::config::Config;
+use crate::additions::Additions;
+use crate::config::{Config, Workspace};
+use crate::message::{GifRequests, HttpClient, MessageBuilder};
+use crate::mutes::Mutes;
+use crate::pause::Pause;
+use crate::skips::Skips;
+use crate::stats::Stats;
+use crate::{post, Globals, Outcome};
+use arc_swap::ArcSwap;
 use async_scoped::spawner::use_tokio::Tokio;
 use async_scoped::{Scope, TokioScope};
-use chrono::Local;
+use chrono::{DateTime, Local};
 use chrono_humanize::HumanTime;
+use rand::Rng;
+use slack_morphism::errors::SlackClientError;
 use slack_morphism::events::{SlackCommandEvent, SlackCommandEventResponse};
 use slack_morphism::listener::{SlackClientEventsListenerEnvironment, SlackClientEventsUserState};
-use slack_morphism::prelude::{HttpStatusCode, SlackHyperClient};
+use slack_morphism::prelude::{
+    HttpStatusCode, SlackApiToken, SlackClientHyperHttpsConnector, SlackHyperClient,
+};
 use slack_morphism::{
-    SlackClientSocketModeConfig, SlackClientSocketModeListener, SlackMessageContent,
-    SlackMessageResponseType, SlackSocketModeListenerCallbacks, UserCallbackResult,
+    SlackChannelId, SlackClientSocketModeConfig, SlackClientSocketModeListener,
+    SlackMessageContent, SlackMessageResponseType, SlackSocketModeListenerCallbacks,
+    UserCallbackResult,
 };
-use std::sync::Arc;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tracing::{debug, info, instrument, trace, warn};
 
+/// Answers a command, from the state of the workspace it was sent from.
+type Handler = for<'a> fn(
+    &'a SlackCommandEvent,
+    &'a SlackClientEventsUserState,
+) -> Pin<Box<dyn Future<Output = SlackCommandEventResponse> + Send + 'a>>;
+
+/// A slash command Beer Bot answers.
+struct Command {
+    name: &'static str,
+
+    /// What follows the name, if anything, e.g. `<index>`.
+    args: &'static str,
+
+    description: &'static str,
+    handler: Handler,
+}
+
+impl Command {
+    /// The name with its args, e.g. `/beer-test <index>`.
+    fn usage(&self) -> String {
+        if self.args.is_empty() {
+            self.name.to_string()
+        } else {
+            format!("{} {}", self.name, self.args)
+        }
+    }
+}
+
+/// Every command, which is both what's answered and what `/beer-help` lists.
+static COMMANDS: [Command; 15] = [
+    Command {
+        name: "/when-can-i-drink",
+        args: "",
+        description: "When the next announcement is, add `exact` for the minutes and seconds",
+        handler: |event, states| Box::pin(handle_when_can_i_drink(event, states)),
+    },
+    Command {
+        name: "/next-beers",
+        args: "",
+        description: "The next few announcements",
+        handler: |event, states| Box::pin(handle_next_beers(event, states)),
+    },
+    Command {
+        name: "/beer-schedule",
+        args: "",
+        description: "Every cron and when it next announces",
+        handler: |event, states| Box::pin(handle_beer_schedule(event, states)),
+    },
+    Command {
+        name: "/beer-now",
+        args: "",
+        description: "Announce beer time now, admins only",
+        handler: |event, states| Box::pin(handle_beer_now(event, states)),
+    },
+    Command {
+        name: "/beer-preview",
+        args: "",
+        description: "Show an announcement only to you",
+        handler: |event, states| Box::pin(handle_beer_preview(event, states)),
+    },
+    Command {
+        name: "/beer-test",
+        args: "<index>",
+        description: "Show an announcement for a message only to you",
+        handler: |event, states| Box::pin(handle_beer_test(event, states)),
+    },
+    Command {
+        name: "/beer-add",
+        args: "<message>",
+        description: "Add a message to pick from",
+        handler: |event, states| Box::pin(handle_beer_add(event, states)),
+    },
+    Command {
+        name: "/beer-gif",
+        args: "<search>",
+        description: "Post a gif for a search",
+        handler: |event, states| Box::pin(handle_beer_gif(event, states)),
+    },
+    Command {
+        name: "/beer-count",
+        args: "",
+        description: "How many announcements have been posted",
+        handler: |event, states| Box::pin(handle_beer_count(event, states)),
+    },
+    Command {
+        name: "/beer-pause",
+        args: "",
+        description: "Pause every cron",
+        handler: |event, states| Box::pin(handle_pause(event, states)),
+    },
+    Command {
+        name: "/beer-resume",
+        args: "",
+        description: "Resume every cron",
+        handler: |event, states| Box::pin(handle_pause(event, states)),
+    },
+    Command {
+        name: "/skip-next",
+        args: "",
+        description: "Skip the next announcement",
+        handler: |event, states| Box::pin(handle_skip_next(event, states)),
+    },
+    Command {
+        name: "/beer-mute",
+        args: "",
+        description: "Stop being pinged by announcements",
+        handler: |event, states| Box::pin(handle_mute(event, states)),
+    },
+    Command {
+        name: "/beer-unmute",
+        args: "",
+        description: "Be pinged by announcements again",
+        handler: |event, states| Box::pin(handle_mute(event, states)),
+    },
+    Command {
+        name: "/beer-help",
+        args: "",
+        description: "This list",
+        handler: |event, states| Box::pin(handle_beer_help(event, states)),
+    },
+];
+
+/// Longest search `/beer-gif` accepts.
+const MAX_GIF_SEARCH_LEN: usize = 50;
+
+/// Number of upcoming announcements listed by `/next-beers`.
+const NEXT_BEERS: usize = 5;
+
+/// Delay before the first retry when the socket fails to connect, doubling after each attempt.
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest delay between attempts to connect the socket.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// How long the socket must stay up before the backoff starts again from the beginning.
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
+
+/// Whether each workspace's socket is connected, by the workspace's channel.
+#[derive(Debug, Default)]
+pub struct Sockets(Mutex<Vec<(SlackChannelId, bool)>>);
+
+impl Sockets {
+    /// Whether every workspace's socket mode listener is connected. Always true when commands are
+    /// disabled, or no workspace has a `socket_token`.
+    #[cfg_attr(not(feature = "healthcheck"), allow(dead_code))]
+    pub fn connected(&self) -> bool {
+        self.0
+            .lock()
+            .expect("Connected sockets poisoned")
+            .iter()
+            .all(|(_, connected)| *connected)
+    }
+
+    /// Record whether the socket for the workspace posting to `channel_id` is connected.
+    fn set_connected(&self, channel_id: &SlackChannelId, connected: bool) {
+        let mut sockets = self.0.lock().expect("Connected sockets poisoned");
+        match sockets.iter_mut().find(|(id, _)| id == channel_id) {
+            Some((_, was)) => *was = connected,
+            None => sockets.push((channel_id.clone(), connected)),
+        }
+    }
+}
+
 #[cfg(feature = "commands")]
-pub fn init<'a>(
-    cfg: Arc<Config>,
-    client: Arc<SlackHyperClient>,
-) -> [(Scope<'a, (), Tokio>, ()); 1] {
-    let callbacks = SlackSocketModeListenerCallbacks::new().with_command_events(handle_commands);
-    let listener_env = Arc::new(
-        SlackClientEventsListenerEnvironment::new(client)
-            .with_user_state(cfg.clone())
-            .with_error_handler(handle_errors),
-    );
-    let listener = SlackClientSocketModeListener::new(
-        &SlackClientSocketModeConfig::new(),
-        listener_env.clone(),
-        callbacks,
-    );
-
-    [unsafe {
-        TokioScope::scope(move |s: &mut Scope<'_, (), Tokio>| {
-            s.spawn_cancellable(
-                async move {
-                    listener
-                        .listen_for(&cfg.socket_token)
-                        .await
-                        .expect("Failed to initialise socket");
-                    info!("listening for commands");
-                    listener.serve().await;
-                },
-                || (),
-            )
+pub fn init<'a>(globals: &Arc<Globals>) -> Vec<(Scope<'a, (), Tokio>, ())> {
+    // Each workspace needs its own socket, with commands answered for that workspace
+    let workspaces = globals.cfg.load().all_workspaces();
+    if workspaces.iter().all(|w| w.socket_token.is_none()) {
+        info!("commands feature built but no socket_token configured");
+    }
+    workspaces
+        .into_iter()
+        .filter_map(|workspace| {
+            let Some(socket_token) = workspace.socket_token.clone() else {
+                debug!(channel = %workspace.channel_id, "no socket_token, not listening for commands");
+                return None;
+            };
+            globals.sockets.set_connected(&workspace.channel_id, false);
+            let channel_id = workspace.channel_id.clone();
+            let sockets = globals.sockets.clone();
+            let callbacks = SlackSocketModeListenerCallbacks::new()
+                .with_hello_events(|_, _, states| handle_hello(states))
+                .with_command_events(handle_commands);
+            #[cfg(feature = "interactions")]
+            let callbacks =
+                callbacks.with_interaction_events(crate::interactions::handle_interactions);
+            let listener_env = Arc::new(
+                SlackClientEventsListenerEnvironment::new(globals.client.clone())
+                    .with_user_state(globals.clone())
+                    .with_user_state(globals.cfg.clone())
+                    .with_user_state(workspace)
+                    .with_user_state(globals.http.clone())
+                    .with_user_state(globals.skips.clone())
+                    .with_user_state(globals.pause.clone())
+                    .with_user_state(globals.stats.clone())
+                    .with_user_state(globals.additions.clone())
+                    .with_user_state(globals.mutes.clone())
+                    .with_error_handler(handle_errors),
+            );
+            let listener = SlackClientSocketModeListener::new(
+                &SlackClientSocketModeConfig::new(),
+                listener_env.clone(),
+                callbacks,
+            );
+
+            Some(unsafe {
+                TokioScope::scope(move |s: &mut Scope<'_, (), Tokio>| {
+                    s.spawn_cancellable(
+                        async move { supervise(&listener, &socket_token, &channel_id, &sockets).await },
+                        || (),
+                    )
+                })
+            })
         })
-    }]
+        .collect()
+}
+
+/// Connect and serve `listener` for the workspace posting to `channel_id`, connecting again with
+/// exponential backoff and jitter whenever connecting fails or serving stops. Dropped sockets are
+/// reconnected by the listener itself, which says hello once they're back.
+#[cfg(feature = "commands")]
+async fn supervise(
+    listener: &SlackClientSocketModeListener<SlackClientHyperHttpsConnector>,
+    token: &SlackApiToken,
+    channel_id: &SlackChannelId,
+    sockets: &Sockets,
+) {
+    let mut backoff = RECONNECT_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let started = Instant::now();
+        match listener.listen_for(token).await {
+            Ok(()) => {
+                info!(%channel_id, "listening for commands");
+                listener.serve().await;
+                warn!(%channel_id, "stopped listening for commands");
+            }
+            Err(e) => warn!(?e, %channel_id, attempt, "failed to connect socket"),
+        }
+        sockets.set_connected(channel_id, false);
+
+        if started.elapsed() >= STABLE_CONNECTION {
+            backoff = RECONNECT_BACKOFF;
+            attempt = 1;
+        }
+        let delay = backoff + backoff.mul_f64(rand::thread_rng().gen_range(0.0..0.5));
+        info!(%channel_id, attempt, ?delay, "connecting socket again");
+        tokio::time::sleep(delay).await;
+        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+        attempt += 1;
+    }
+}
+
+/// Mark the workspace's socket connected once Slack says hello, including after it reconnects.
+async fn handle_hello(states: SlackClientEventsUserState) {
+    let channel_id = workspace(&states).await.channel_id;
+    debug!(%channel_id, "socket connected");
+    globals(&states)
+        .await
+        .sockets
+        .set_connected(&channel_id, true);
 }
 
 #[cfg(not(feature = "commands"))]
 #[inline]
-pub fn init<'a>(_: Arc<Config>, _: Arc<SlackHyperClient>) -> [(Scope<'a, (), Tokio>, ()); 0] {
-    []
+pub fn init<'a>(_: &Arc<Globals>) -> Vec<(Scope<'a, (), Tokio>, ())> {
+    Vec::new()
 }
 
 #[instrument(skip_all)]
 fn handle_errors(
     err: Box<dyn std::error::Error + Send + Sync>,
     _client: Arc<SlackHyperClient>,
-    _states: SlackClientEventsUserState,
+    states: SlackClientEventsUserState,
 ) -> HttpStatusCode {
     warn!("{:?}", err);
+    // The listener reconnects the socket itself, saying hello once it's back
+    if let Some(SlackClientError::SocketModeProtocolError(_)) = err.downcast_ref() {
+        if let Ok(states) = states.try_read() {
+            if let (Some(globals), Some(workspace)) = (
+                states.get_user_state::<Arc<Globals>>(),
+                states.get_user_state::<Workspace>(),
+            ) {
+                globals.sockets.set_connected(&workspace.channel_id, false);
+            }
+        }
+    }
 
     HttpStatusCode::OK
 }
@@ -72,27 +328,572 @@ async fn handle_commands(
     states: SlackClientEventsUserState,
 ) -> UserCallbackResult<SlackCommandEventResponse> {
     debug!("command received");
-    Ok(match event.command.0.as_str() {
-        "/when-can-i-drink" => {
-            let now = Local::now();
-            let next = states
-                .read()
+    let allowed = &config(&states).await.command_channels;
+    if !allowed.is_empty() && !allowed.contains(&event.channel_id) {
+        debug!(channel = %event.channel_id, "command used outside command_channels");
+        return Ok(SlackCommandEventResponse::new(
+            SlackMessageContent::new().with_text("Not allowed here".to_string()),
+        )
+        .with_response_type(SlackMessageResponseType::Ephemeral));
+    }
+
+    Ok(
+        match COMMANDS
+            .iter()
+            .find(|command| command.name == event.command.0)
+        {
+            Some(command) => (command.handler)(&event, &states).await,
+            None => SlackCommandEventResponse::new(
+                SlackMessageContent::new().with_text("Dunno that one, try /beer-help".to_string()),
+            ),
+        },
+    )
+}
+
+async fn handle_when_can_i_drink(
+    event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let now = Local::now();
+    let exact = event.text.as_deref().map(str::trim) == Some("exact");
+    let next = states
+        .read()
+        .await
+        .get_user_state::<Arc<ArcSwap<Config>>>()
+        .expect("Unable to get config")
+        .load()
+        .next_drink(now)
+        .map(|dt| {
+            if exact {
+                exact_until(dt - now)
+            } else {
+                HumanTime::from(dt - now).to_string()
+            }
+        })
+        .unwrap_or_else(|| "in some time".to_string());
+    trace!(next = next);
+    SlackCommandEventResponse::new(SlackMessageContent::new().with_text(next))
+        .with_response_type(SlackMessageResponseType::InChannel)
+}
+
+async fn handle_next_beers(
+    _event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let now = Local::now();
+    let state = states.read().await;
+    let cfg = state
+        .get_user_state::<Arc<ArcSwap<Config>>>()
+        .expect("Unable to get config")
+        .load();
+    let next = upcoming(&cfg, now, NEXT_BEERS)
+        .into_iter()
+        .map(|dt| {
+            format!(
+                "{} ({})",
+                HumanTime::from(dt - now),
+                dt.format("%a %d %b %H:%M")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    trace!(next = next);
+    SlackCommandEventResponse::new(SlackMessageContent::new().with_text(next))
+        .with_response_type(SlackMessageResponseType::InChannel)
+}
+
+async fn handle_beer_now(
+    event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let cfg = config(states).await;
+    let globals = globals(states).await;
+    let text = if !cfg.admin_users.contains(&event.user_id) {
+        "not authorised"
+    } else {
+        match beer_now(&cfg, &workspace(states).await, &globals).await {
+            Ok(Outcome::Posted) => "Beer time announced 🍺",
+            Ok(Outcome::TooSoon) => "Beer time was announced too recently",
+            Ok(Outcome::Duplicate) => "That message was just announced",
+            Ok(Outcome::Failed) => "Failed to announce beer time",
+            Ok(Outcome::DryRun) => "Dry run is on, so nothing was posted",
+            Err(e) => {
+                warn!(?e, "failed to send message");
+                "Failed to announce beer time"
+            }
+        }
+    };
+    SlackCommandEventResponse::new(SlackMessageContent::new().with_text(text.to_string()))
+        .with_response_type(SlackMessageResponseType::Ephemeral)
+}
+
+async fn handle_beer_schedule(
+    _event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let now = Local::now();
+    let cfg = config(states).await;
+    let schedule = cfg
+        .crons
+        .iter()
+        .map(|job| match job.next_occurrence(now) {
+            _ if !job.enabled => format!("`{}` disabled", job),
+            Some(dt) => format!(
+                "`{}` next {} ({})",
+                job,
+                HumanTime::from(dt - now),
+                dt.format("%a %d %b %H:%M")
+            ),
+            None => format!("`{}` disabled, never fires again", job),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    trace!(schedule);
+    SlackCommandEventResponse::new(SlackMessageContent::new().with_text(schedule))
+        .with_response_type(SlackMessageResponseType::Ephemeral)
+}
+
+async fn handle_beer_preview(
+    _event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let cfg = config(states).await;
+    let http = http(states).await;
+    let additions = additions(states).await;
+    let content = match MessageBuilder::new(&cfg, &http)
+        .with_gif_requests(&globals(states).await.gif_requests)
+        .with_additions(&additions)
+        .build_preview()
+        .await
+    {
+        Ok(content) => content,
+        Err(e) => {
+            warn!(?e, "failed to build preview");
+            SlackMessageContent::new().with_text("Failed to build a preview".to_string())
+        }
+    };
+    SlackCommandEventResponse::new(content).with_response_type(SlackMessageResponseType::Ephemeral)
+}
+
+async fn handle_beer_test(
+    event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let cfg = config(states).await;
+    let index = event.text.as_deref().unwrap_or_default().trim();
+    let content = match index.parse::<usize>() {
+        Ok(index) if index < cfg.messages.len() => {
+            match MessageBuilder::new(&cfg, &http(states).await)
+                .with_gif_requests(&globals(states).await.gif_requests)
+                .with_message(index)
+                .build_preview()
                 .await
-                .get_user_state::<Arc<Config>>()
-                .expect("Unable to get config")
-                .crons
-                .iter()
-                .filter_map(|s| s.upcoming(Local).next())
-                .map(|dt| dt - now)
-                .min()
-                .map(|d| HumanTime::from(d).to_string())
-                .unwrap_or_else(|| "in some time".to_string());
-            trace!(next = next);
-            SlackCommandEventResponse::new(SlackMessageContent::new().with_text(next))
-                .with_response_type(SlackMessageResponseType::InChannel)
+            {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!(?e, index, "failed to build test message");
+                    SlackMessageContent::new()
+                        .with_text(format!("Failed to build message {}", index))
+                }
+            }
+        }
+        _ => SlackMessageContent::new().with_text(format!(
+            "Usage: /beer-test <index>, where index is from 0 to {}",
+            cfg.messages.len().saturating_sub(1)
+        )),
+    };
+    SlackCommandEventResponse::new(content).with_response_type(SlackMessageResponseType::Ephemeral)
+}
+
+async fn handle_beer_add(
+    event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let cfg = config(states).await;
+    let message = event.text.as_deref().unwrap_or_default().trim();
+    let text = if !cfg.anyone_can_add && !cfg.admin_users.contains(&event.user_id) {
+        "not authorised".to_string()
+    } else if message.is_empty() {
+        "Usage: /beer-add <message>".to_string()
+    } else {
+        match additions(states).await.add(message.to_string()).await {
+            Ok(()) => format!("Added \"{}\" to the messages", message),
+            Err(e) => {
+                warn!(?e, "failed to persist added message");
+                format!(
+                    "Added \"{}\" to the messages, but it will be gone after restarting",
+                    message
+                )
+            }
+        }
+    };
+    trace!(text);
+    SlackCommandEventResponse::new(SlackMessageContent::new().with_text(text))
+        .with_response_type(SlackMessageResponseType::Ephemeral)
+}
+
+async fn handle_beer_gif(
+    event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let search = gif_search(event.text.as_deref().unwrap_or_default());
+    let content = if search.is_empty() || search.chars().count() > MAX_GIF_SEARCH_LEN {
+        Err(format!(
+            "Usage: /beer-gif <search>, with up to {} letters, numbers, spaces, - or '",
+            MAX_GIF_SEARCH_LEN
+        ))
+    } else {
+        let cfg = config(states).await;
+        let globals = globals(states).await;
+        beer_gif(&cfg, &globals.http, &globals.gif_requests, &search).await
+    };
+    match content {
+        Ok(content) => SlackCommandEventResponse::new(content)
+            .with_response_type(SlackMessageResponseType::InChannel),
+        Err(text) => SlackCommandEventResponse::new(SlackMessageContent::new().with_text(text))
+            .with_response_type(SlackMessageResponseType::Ephemeral),
+    }
+}
+
+async fn handle_beer_count(
+    _event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let counts = stats(states).await.counts();
+    let text = match counts.first_post {
+        Some(first) => format!(
+            "{} beers announced since {}",
+            counts.total,
+            first.with_timezone(&Local).format("%a %d %b %Y")
+        ),
+        None => "No beers announced yet".to_string(),
+    };
+    trace!(text);
+    SlackCommandEventResponse::new(SlackMessageContent::new().with_text(text))
+        .with_response_type(SlackMessageResponseType::InChannel)
+}
+
+async fn handle_pause(
+    event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let paused = event.command.0 == "/beer-pause";
+    let pause = states
+        .read()
+        .await
+        .get_user_state::<Arc<Pause>>()
+        .expect("Unable to get pause")
+        .clone();
+    let text = match pause.set(paused).await {
+        Ok(was) if was == paused => format!("Beer Bot is already {}", pause_state(paused)),
+        Ok(_) => format!("Beer Bot is now {}", pause_state(paused)),
+        Err(e) => {
+            warn!(?e, "failed to persist paused state");
+            format!(
+                "Beer Bot is now {}, but will be {} after restarting",
+                pause_state(paused),
+                pause_state(!paused)
+            )
         }
-        _ => SlackCommandEventResponse::new(
-            SlackMessageContent::new().with_text("Dunno that one".to_string()),
+    };
+    trace!(text);
+    SlackCommandEventResponse::new(SlackMessageContent::new().with_text(text))
+        .with_response_type(SlackMessageResponseType::InChannel)
+}
+
+async fn handle_mute(
+    event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let muted = event.command.0 == "/beer-mute";
+    let mutes = states
+        .read()
+        .await
+        .get_user_state::<Arc<Mutes>>()
+        .expect("Unable to get mutes")
+        .clone();
+    let text = match mutes.set(&event.user_id, muted).await {
+        Ok(was) if was == muted => format!("You're already {}", mute_state(muted)),
+        Ok(_) => format!("You're now {}", mute_state(muted)),
+        Err(e) => {
+            warn!(?e, "failed to persist muted users");
+            format!(
+                "You're now {}, but will be {} after restarting",
+                mute_state(muted),
+                mute_state(!muted)
+            )
+        }
+    };
+    trace!(text);
+    SlackCommandEventResponse::new(SlackMessageContent::new().with_text(text))
+        .with_response_type(SlackMessageResponseType::Ephemeral)
+}
+
+async fn handle_skip_next(
+    _event: &SlackCommandEvent,
+    states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let now = Local::now();
+    let cfg = config(states).await;
+    let skips = states
+        .read()
+        .await
+        .get_user_state::<Arc<Skips>>()
+        .expect("Unable to get skips")
+        .clone();
+    // One more of each cron's announcements than are skipped, so there's one left to skip
+    let n = skips.count() + 1;
+    let upcoming = cfg
+        .crons
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, job)| job.upcoming(now).take(n).map(move |dt| (idx, dt)));
+    let text = match skips.skip_next(now, upcoming) {
+        Some((_, dt)) => format!(
+            "Skipping the announcement {} ({})",
+            HumanTime::from(dt - now),
+            dt.format("%a %d %b %H:%M")
         ),
+        None => "No announcements to skip".to_string(),
+    };
+    trace!(text);
+    SlackCommandEventResponse::new(SlackMessageContent::new().with_text(text))
+        .with_response_type(SlackMessageResponseType::InChannel)
+}
+
+async fn handle_beer_help(
+    _event: &SlackCommandEvent,
+    _states: &SlackClientEventsUserState,
+) -> SlackCommandEventResponse {
+    let help = COMMANDS
+        .iter()
+        .map(|command| format!("`{}` - {}", command.usage(), command.description))
+        .collect::<Vec<_>>()
+        .join("\n");
+    SlackCommandEventResponse::new(SlackMessageContent::new().with_text(help))
+        .with_response_type(SlackMessageResponseType::Ephemeral)
+}
+
+/// `until` in whole minutes and seconds, for when "in a few minutes" is too vague.
+fn exact_until(until: chrono::Duration) -> String {
+    let secs = until.num_seconds().max(0);
+    format!("in {} minutes and {} seconds", secs / 60, secs % 60)
+}
+
+/// `text` with only letters, numbers, `-` and `'`, separated by single spaces.
+fn gif_search(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '\'')
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A message with a gif for `search`, or why there isn't one.
+#[cfg(feature = "giphy")]
+async fn beer_gif(
+    cfg: &Config,
+    http: &HttpClient,
+    requests: &GifRequests,
+    search: &str,
+) -> Result<SlackMessageContent, String> {
+    MessageBuilder::new(cfg, http)
+        .with_gif_requests(requests)
+        .build_gif(search)
+        .await
+        .map_err(|e| {
+            warn!(?e, search, "failed to get gif");
+            format!("Couldn't find a gif for {}", search)
+        })
+}
+
+#[cfg(not(feature = "giphy"))]
+async fn beer_gif(
+    _: &Config,
+    _: &HttpClient,
+    _: &GifRequests,
+    _: &str,
+) -> Result<SlackMessageContent, String> {
+    Err("Gifs aren't enabled".to_string())
+}
+
+fn pause_state(paused: bool) -> &'static str {
+    if paused {
+        "paused"
+    } else {
+        "running"
+    }
+}
+
+fn mute_state(muted: bool) -> &'static str {
+    if muted {
+        "muted, and won't be pinged by announcements"
+    } else {
+        "unmuted, and will be pinged by announcements"
+    }
+}
+
+/// The next `n` announcements after `now` across every cron, soonest first.
+fn upcoming(cfg: &Config, now: DateTime<Local>, n: usize) -> Vec<DateTime<Local>> {
+    let mut upcoming = cfg
+        .crons
+        .iter()
+        .flat_map(|c| c.upcoming(now).take(n))
+        .collect::<Vec<_>>();
+    upcoming.sort();
+    upcoming.truncate(n);
+    upcoming
+}
+
+/// The current config.
+async fn config(states: &SlackClientEventsUserState) -> Arc<Config> {
+    states
+        .read()
+        .await
+        .get_user_state::<Arc<ArcSwap<Config>>>()
+        .expect("Unable to get config")
+        .load_full()
+}
+
+/// The client shared by every [`MessageBuilder`].
+async fn http(states: &SlackClientEventsUserState) -> HttpClient {
+    states
+        .read()
+        .await
+        .get_user_state::<HttpClient>()
+        .expect("Unable to get HTTP client")
+        .clone()
+}
+
+/// State shared with the crons.
+async fn globals(states: &SlackClientEventsUserState) -> Arc<Globals> {
+    states
+        .read()
+        .await
+        .get_user_state::<Arc<Globals>>()
+        .expect("Unable to get globals")
+        .clone()
+}
+
+/// Messages added with `/beer-add`.
+async fn additions(states: &SlackClientEventsUserState) -> Arc<Additions> {
+    states
+        .read()
+        .await
+        .get_user_state::<Arc<Additions>>()
+        .expect("Unable to get added messages")
+        .clone()
+}
+
+async fn stats(states: &SlackClientEventsUserState) -> Arc<Stats> {
+    states
+        .read()
+        .await
+        .get_user_state::<Arc<Stats>>()
+        .expect("Unable to get stats")
+        .clone()
+}
+
+/// The workspace the command was sent from.
+async fn workspace(states: &SlackClientEventsUserState) -> Workspace {
+    states
+        .read()
+        .await
+        .get_user_state::<Workspace>()
+        .expect("Unable to get workspace")
+        .clone()
+}
+
+/// Announce beer time in the workspace's channel immediately, the same way crons do.
+async fn beer_now(
+    cfg: &Config,
+    workspace: &Workspace,
+    globals: &Globals,
+) -> anyhow::Result<Outcome> {
+    let mut builder = MessageBuilder::new(cfg, &globals.http)
+        .with_gif_requests(&globals.gif_requests)
+        .with_additions(&globals.additions);
+    // Share the next cron's picks, so it doesn't then repeat this message
+    if let Some(idx) = cfg.next_cron(Local::now()) {
+        builder = builder.with_picks(globals.picks.get(idx, &workspace.token));
+    }
+    let announcement = builder.build_announcement().await?;
+    if cfg.dry_run {
+        info!(
+            content = serde_json::to_string(&announcement.content)?,
+            "dry run, not sending"
+        );
+        return Ok(Outcome::DryRun);
+    }
+
+    Ok(match post::poster(cfg, &globals.http, &workspace.token) {
+        Some(poster) => {
+            crate::announce_with(
+                None,
+                &workspace.token,
+                poster.as_ref(),
+                &announcement,
+                cfg,
+                globals,
+            )
+            .await
+        }
+        None => {
+            crate::announce(
+                None,
+                &workspace.token,
+                &workspace.channel_id,
+                &announcement,
+                cfg,
+                globals,
+            )
+            .await
+        }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connected_only_while_every_socket_is() {
+        let first = SlackChannelId("C1".to_string());
+        let second = SlackChannelId("C2".to_string());
+        let sockets = Sockets::default();
+        sockets.set_connected(&first, false);
+        sockets.set_connected(&second, false);
+        assert!(!sockets.connected());
+
+        sockets.set_connected(&first, true);
+        assert!(!sockets.connected());
+        sockets.set_connected(&second, true);
+        assert!(sockets.connected());
+
+        sockets.set_connected(&first, false);
+        assert!(!sockets.connected());
+    }
+
+    #[test]
+    fn commands_are_answered_once() {
+        for (idx, command) in COMMANDS.iter().enumerate() {
+            assert!(command.name.starts_with('/'), "{}", command.name);
+            assert!(
+                COMMANDS[idx + 1..].iter().all(|c| c.name != command.name),
+                "{} is registered twice",
+                command.name
+            );
+        }
+    }
+
+    #[test]
+    fn usage_includes_args() {
+        let usage = COMMANDS.iter().map(Command::usage).collect::<Vec<_>>();
+        assert!(usage.contains(&"/beer-test <index>".to_string()));
+        assert!(usage.contains(&"/beer-help".to_string()));
+    }
+}
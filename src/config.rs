@@ -1,50 +1,526 @@
+use std::borrow::Cow;
 use std::env;
 use std::fmt::{Debug, Display, Formatter};
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, Weekday};
+use chrono_tz::Tz;
 use config::builder::AsyncState;
 use config::{
     AsyncSource, ConfigBuilder, ConfigError, Environment, FileFormat, Format, Map, Value,
 };
-use cron::Schedule;
+use cron::{Schedule, TimeUnitSpec};
 use derive_more::Debug as DeriveDebug;
 use serde::de::{Error, Visitor};
 use serde::{Deserialize, Deserializer};
 use serde_with::serde_as;
-use serde_with::DisplayFromStr;
-use slack_morphism::{SlackApiToken, SlackApiTokenValue, SlackChannelId};
-use tracing::instrument;
+use slack_morphism::blocks::SlackBlock;
+use slack_morphism::{
+    SlackApiToken, SlackApiTokenValue, SlackChannelId, SlackTs, SlackUserGroupId, SlackUserId,
+};
+use tracing::{instrument, warn};
+#[cfg(any(
+    feature = "giphy",
+    feature = "audit",
+    feature = "webhook",
+    feature = "discord"
+))]
+use url::Url;
+
+#[cfg(feature = "giphy")]
+use crate::gif::GifProviderKind;
+#[cfg(feature = "giphy")]
+use crate::giphy::GiphyKind;
 
 #[serde_as]
 #[derive(DeriveDebug, Deserialize)]
 pub struct Config {
+    /// Only optional if `webhook_url` is set, or `platform` is `discord`.
+    #[cfg_attr(
+        any(feature = "webhook", feature = "discord"),
+        serde(default = "empty_token")
+    )]
     #[serde(deserialize_with = "deserialize_token")]
     #[debug("len({})", token.token_value.0.len())]
     pub token: SlackApiToken,
 
+    /// Commands are only listened for when this is set.
     #[cfg(feature = "commands")]
-    #[serde(deserialize_with = "deserialize_token")]
-    #[debug("len({})", socket_token.token_value.0.len())]
-    pub socket_token: SlackApiToken,
+    #[serde(default, deserialize_with = "deserialize_opt_token")]
+    #[debug("{:?}", socket_token.as_ref().map(|t| t.token_value.0.len()))]
+    pub socket_token: Option<SlackApiToken>,
 
     #[cfg(feature = "giphy")]
+    #[serde(default)]
     #[debug("len({})", giphy_token.len())]
     pub giphy_token: String,
 
-    #[serde_as(as = "Vec<DisplayFromStr>")]
-    pub crons: Vec<Schedule>,
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    #[debug("len({})", tenor_token.len())]
+    pub tenor_token: String,
+
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    pub gif_provider: GifProviderKind,
+
+    /// Gif providers to try in order, until one has a gif. Replaces `gif_provider` if set.
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    pub gif_providers: Vec<GifProviderKind>,
+
+    #[serde(deserialize_with = "deserialize_crons")]
+    pub crons: Vec<CronJob>,
 
     pub channel_id: SlackChannelId,
 
-    pub messages: Vec<String>,
+    /// Other workspaces to announce in, as well as the one `token` is for.
+    #[serde(default)]
+    pub workspaces: Vec<Workspace>,
+
+    pub messages: Vec<Message>,
 
     #[cfg(feature = "giphy")]
-    pub gif_searches: Vec<String>,
+    #[serde(default)]
+    pub gif_searches: Vec<GifSearch>,
+
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    pub giphy_kind: GiphyKind,
+
+    /// How gifs are shown in announcements.
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    pub giphy_render: GiphyRender,
+
+    /// Whether to show the message above the gif, rather than only in notifications.
+    #[cfg(feature = "giphy")]
+    #[serde(default = "default_show_header")]
+    pub show_header: bool,
+
+    /// Giphy's API, or a proxy for it.
+    #[cfg(feature = "giphy")]
+    #[serde(default = "default_giphy_base_url")]
+    pub giphy_base_url: Url,
+
+    #[cfg(feature = "giphy")]
+    #[serde(default = "default_giphy_rating")]
+    pub giphy_rating: String,
+
+    /// Caption shown under Giphy gifs instead of "Powered By GIPHY".
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    pub giphy_attribution: Option<String>,
+
+    /// Reply to each announcement with where its gif is from.
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    pub giphy_source_reply: bool,
+
+    /// Largest gif to send, preferring smaller renditions of bigger ones.
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    pub giphy_max_bytes: Option<u64>,
+
+    /// Widest gif to send in pixels, preferring smaller renditions of bigger ones.
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    pub giphy_max_width: Option<u32>,
+
+    /// Tallest gif to send in pixels, preferring smaller renditions of bigger ones.
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    pub giphy_max_height: Option<u32>,
+
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    pub giphy_cache_size: usize,
+
+    #[cfg(feature = "giphy")]
+    #[serde(default = "default_giphy_cache_refresh")]
+    pub giphy_cache_refresh: f64,
+
+    /// Image to show instead when a gif can't be found.
+    #[cfg(feature = "giphy")]
+    #[serde(default)]
+    pub fallback_gif_url: Option<Url>,
+
+    /// Users allowed to use admin only commands.
+    #[serde(default)]
+    pub admin_users: Vec<SlackUserId>,
+
+    /// Channels commands can be used in. Anywhere if empty.
+    #[serde(default)]
+    pub command_channels: Vec<SlackChannelId>,
 
     #[serde(default)]
     pub log: String,
+
+    /// Format of logs written to stdout.
+    #[serde(default)]
+    pub log_format: LogFormat,
+
+    #[cfg(feature = "healthcheck")]
+    pub health_addr: std::net::SocketAddr,
+
+    #[cfg(feature = "metrics")]
+    pub metrics_addr: std::net::SocketAddr,
+
+    #[serde(default)]
+    pub dry_run: bool,
+
+    #[serde(default)]
+    pub thread_mode: bool,
+
+    /// Post to every workspace's channel once started, to confirm it's running.
+    #[serde(default)]
+    pub announce_on_start: bool,
+
+    #[serde(default)]
+    pub use_slack_scheduler: bool,
+
+    #[serde(default = "default_post_retries")]
+    pub post_retries: u32,
+
+    #[serde(default = "default_message_history")]
+    pub message_history: usize,
+
+    /// How messages are picked for announcements.
+    #[serde(default)]
+    pub message_strategy: MessageStrategy,
+
+    /// Seed for picking messages and gif searches, so the same are picked every run.
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
+
+    /// Where to send a record of every announcement.
+    #[cfg(feature = "audit")]
+    #[serde(default)]
+    pub audit_webhook: Option<Url>,
+
+    /// Incoming Webhook to announce with when there's no `token`.
+    #[cfg(feature = "webhook")]
+    #[serde(default)]
+    #[debug("{:?}", webhook_url.as_ref().map(|_| "set"))]
+    pub webhook_url: Option<Url>,
+
+    /// Where announcements are posted.
+    #[cfg(feature = "discord")]
+    #[serde(default)]
+    pub platform: Platform,
+
+    /// Discord webhook to announce with when `platform` is `discord`.
+    #[cfg(feature = "discord")]
+    #[serde(default)]
+    #[debug("{:?}", discord_webhook_url.as_ref().map(|_| "set"))]
+    pub discord_webhook_url: Option<Url>,
+
+    /// Program and arguments to run after each announcement is posted.
+    #[cfg(feature = "exec")]
+    #[serde(default)]
+    pub on_post_command: Vec<String>,
+
+    /// Show which cron sent each announcement at the bottom of it.
+    #[serde(default)]
+    pub debug_footer: bool,
+
+    /// Emoji names to react to each announcement with.
+    #[serde(default)]
+    pub auto_reactions: Vec<String>,
+
+    /// Emoji to randomly pick from to put either side of the message, e.g. `🍷` or `:coffee:`.
+    #[serde(default)]
+    pub decoration_emoji: Vec<String>,
+
+    /// Put before every message, e.g. `🍺 [BeerBot] `, with placeholders substituted.
+    #[serde(default)]
+    pub message_prefix: String,
+
+    /// Put after every message, with placeholders substituted.
+    #[serde(default)]
+    pub message_suffix: String,
+
+    /// Let anyone use `/beer-add`, rather than only `admin_users`.
+    #[serde(default)]
+    pub anyone_can_add: bool,
+
+    /// Fewest seconds between announcements in a channel.
+    #[serde(default)]
+    pub min_interval_seconds: u64,
+
+    /// Seconds within which the same message picked for the same channel is skipped, e.g. when
+    /// crons fire together.
+    #[serde(default)]
+    pub dedup_window_seconds: u64,
+
+    /// Times of day never to announce in, even if a cron fires.
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+
+    /// File to keep stats in. Defaults to one in the data dir.
+    #[serde(default)]
+    pub stats_path: Option<PathBuf>,
+
+    /// Most seconds to randomly delay each announcement by.
+    #[serde(default)]
+    pub jitter_seconds: u64,
+
+    /// Longest to wait for crons to finish sending when stopping or reloading, before cancelling.
+    #[serde(default = "default_shutdown_timeout_ms")]
+    pub shutdown_timeout_ms: u64,
+
+    /// What `${VAR}` in messages becomes when `VAR` isn't set.
+    #[serde(default)]
+    pub unset_env_vars: UnsetEnvVars,
+
+    /// How this was loaded, so reloading loads it the same way.
+    #[serde(skip)]
+    pub source: Source,
+}
+
+/// How a config was loaded.
+#[derive(Debug, Clone, Default)]
+pub enum Source {
+    /// Built by the caller, e.g. when embedding, so there's nowhere to reload it from.
+    #[default]
+    Given,
+
+    /// Loaded by [`Config::new`] from the config dir and environment variables, and `path` if
+    /// given.
+    Loaded { path: Option<PathBuf> },
+}
+
+/// A Slack workspace to announce in.
+#[derive(DeriveDebug, Clone, Deserialize)]
+pub struct Workspace {
+    #[serde(deserialize_with = "deserialize_token")]
+    #[debug("len({})", token.token_value.0.len())]
+    pub token: SlackApiToken,
+
+    #[cfg(feature = "commands")]
+    #[serde(default, deserialize_with = "deserialize_opt_token")]
+    #[debug("{:?}", socket_token.as_ref().map(|t| t.token_value.0.len()))]
+    pub socket_token: Option<SlackApiToken>,
+
+    pub channel_id: SlackChannelId,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CronJob {
+    #[serde(deserialize_with = "deserialize_schedule")]
+    pub schedule: ScheduleKind,
+
+    /// Name to tell this cron apart by, such as in `debug_footer`.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Name to post as instead of the bot's. Requires the `chat:write.customize` scope.
+    #[serde(default)]
+    pub username: Option<String>,
+
+    /// Emoji to post with instead of the bot's icon, e.g. `:sunrise:`.
+    #[serde(default)]
+    pub icon_emoji: Option<String>,
+
+    /// Whether this cron announces at all.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub channel_id: Option<SlackChannelId>,
+
+    /// More channels to post the same announcement to.
+    #[serde(default)]
+    pub channel_ids: Vec<SlackChannelId>,
+
+    /// IANA timezone the schedule is in. Defaults to the local timezone.
+    #[serde(default)]
+    pub timezone: Option<Tz>,
+
+    #[serde(default)]
+    pub notify: Notify,
+
+    /// User group to mention, as well as `notify`.
+    #[serde(default)]
+    pub user_group: Option<SlackUserGroupId>,
+
+    /// Dates not to announce on, in this cron's timezone.
+    #[serde(default)]
+    pub skip_dates: Vec<NaiveDate>,
+
+    #[serde(default)]
+    pub skip_weekdays: Vec<Weekday>,
+
+    /// Message to always reply to, instead of posting to the channel, given as its timestamp or
+    /// permalink.
+    #[serde(default, deserialize_with = "deserialize_thread_ts")]
+    pub thread_ts: Option<SlackTs>,
+}
+
+/// Units `@every` intervals can be in, with their length in seconds, longest first.
+const INTERVAL_UNITS: [(&str, u64); 4] = [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60), ("s", 1)];
+
+/// Longest `@every` interval, well short of where counting intervals in milliseconds overflows.
+const MAX_INTERVAL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// When a cron fires: either a cron expression, or `@every` interval counted from `from`, which is
+/// when beer-bot started once running.
+#[derive(Debug, Clone)]
+pub enum ScheduleKind {
+    Cron(Box<Schedule>),
+    Interval {
+        every: Duration,
+        from: DateTime<Local>,
+    },
+}
+
+/// Window of the day not to announce in, which can wrap past midnight e.g. 22:00 to 07:00.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+/// Who to mention in announcements.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Notify {
+    #[default]
+    None,
+    Here,
+    Channel,
+}
+
+/// Where announcements are posted.
+#[cfg(feature = "discord")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    #[default]
+    Slack,
+
+    /// A Discord webhook, with only the message text and gif.
+    Discord,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+
+    /// Line delimited JSON.
+    Json,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageStrategy {
+    /// Weighted randomly, avoiding the last `message_history` messages.
+    #[default]
+    Random,
+
+    /// Each message in turn.
+    Sequential,
+
+    /// The same message all day, changing each day.
+    Daily,
+}
+
+#[cfg(feature = "giphy")]
+#[derive(Debug, Deserialize)]
+#[serde(from = "GifSearchEntry")]
+pub struct GifSearch {
+    pub tag: String,
+
+    /// Relative chance of this search being picked. A weight of 0 disables the search.
+    pub weight: u32,
+}
+
+/// A gif search is either a bare tag or a table with a weight.
+#[cfg(feature = "giphy")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum GifSearchEntry {
+    Bare(String),
+    Weighted {
+        tag: String,
+        #[serde(default = "default_weight")]
+        weight: u32,
+    },
+}
+
+/// How gifs are shown in announcements.
+#[cfg(feature = "giphy")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GiphyRender {
+    /// An image block.
+    #[default]
+    Block,
+
+    /// The gif's URL, left for Slack to unfurl, which animates better in some clients.
+    Unfurl,
+}
+
+/// What to replace `${VAR}` with when `VAR` isn't set.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnsetEnvVars {
+    /// Leave `${VAR}` as is.
+    #[default]
+    Keep,
+
+    Empty,
+}
+
+/// A cron is either a bare cron expression or a table with extra per cron options.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CronEntry {
+    Bare(String),
+    Job(Box<CronJob>),
+
+    /// A table which isn't a valid cron, kept to say which entry is wrong.
+    Invalid {
+        schedule: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "MessageEntry")]
+pub struct Message {
+    pub text: String,
+
+    /// Relative chance of this message being picked. A weight of 0 disables the message.
+    pub weight: u32,
+
+    /// Block Kit JSON to send instead of the usual layout, with `text` only shown in notifications.
+    pub blocks: Option<String>,
+
+    /// Weekdays this message is only picked on, in the cron's timezone. Any day if empty.
+    pub weekdays: Vec<Weekday>,
+}
+
+/// A message is either bare text or a table with a weight.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MessageEntry {
+    Bare(String),
+    Weighted {
+        #[serde(default)]
+        text: String,
+        #[serde(default = "default_weight")]
+        weight: u32,
+        #[serde(default)]
+        blocks: Option<String>,
+        #[serde(default)]
+        weekdays: Vec<Weekday>,
+    },
 }
 
 #[derive(Debug)]
@@ -55,103 +531,759 @@ struct AsyncFileSource<F: Format + Debug, P: AsRef<Path> + Debug> {
 
 struct SlackApiTokenVisitor;
 
+/// Content ratings accepted by Giphy, or none to not filter gifs.
+#[cfg(feature = "giphy")]
+const GIPHY_RATINGS: [&str; 5] = ["g", "pg", "pg-13", "r", crate::giphy::NO_RATING];
+
+/// Argument to only load and validate the config, then exit.
+pub const VALIDATE_FLAG: &str = "--validate";
+
+/// Keys which can instead be read from the file given by `<key>_file`.
+const FILE_KEYS: [&str; 3] = ["token", "socket_token", "giphy_token"];
+
+/// Environment variable to override `ENV_PREFIX` with, read before the rest of the config.
+const ENV_PREFIX_VAR: &str = "BEERBOT_CONFIG_PREFIX";
+
+/// Prefix of environment variables read as config, unless overridden by `ENV_PREFIX_VAR`.
+const ENV_PREFIX: &str = "BEERBOT";
+
+/// Environment variable to override `LIST_SEPARATOR` with, read before the rest of the config.
+const LIST_SEPARATOR_VAR: &str = "BEERBOT_LIST_SEP";
+
+/// Separator between items of lists in environment variables, unless overridden by
+/// `LIST_SEPARATOR_VAR`.
+const LIST_SEPARATOR: &str = "¬";
+
+/// Config file names searched for in the config dir, in order of preference.
+const CONFIG_FILES: [&str; 4] = [
+    "beerbot.toml",
+    "beerbot.yaml",
+    "beerbot.yml",
+    "beerbot.json",
+];
+
+/// Times to try reading a config file before giving up, e.g. while a network mount comes up.
+const FILE_READ_ATTEMPTS: u32 = 3;
+
+/// Wait between attempts at reading a config file.
+const FILE_READ_RETRY_DELAY: Duration = Duration::from_secs(2);
+
 impl Config {
+    /// Load the config from the config dir and environment variables, along with `path` if given,
+    /// e.g. from the command line.
     #[instrument]
-    pub async fn new() -> Result<Config> {
+    pub async fn new(path: Option<&Path>) -> Result<Config> {
         let mut config_builder = ConfigBuilder::<AsyncState>::default();
+        let mut default_path = None;
 
         if let Some(dirs) = directories::ProjectDirs::from("com", "beerbot", "beerbot") {
-            let path = dirs.config_local_dir().join("beerbot.toml");
-            if tokio::fs::try_exists(&path).await.unwrap_or(false) {
-                config_builder = config_builder.add_async_source(AsyncFileSource {
-                    format: FileFormat::Toml,
-                    file: path,
-                });
-            } else {
+            let mut found = false;
+            for name in CONFIG_FILES {
+                let path = dirs.config_local_dir().join(name);
+                if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+                    config_builder = config_builder.add_async_source(AsyncFileSource::new(path));
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                let path = dirs.config_local_dir().join(CONFIG_FILES[0]);
                 eprintln!("Config not found, skipping. {}", path.display());
+                default_path = Some(path);
             }
         }
 
-        let tmp = env::args().nth(1);
-        if let Some(cfg_path) = tmp {
-            let path = PathBuf::from(cfg_path);
-            if tokio::fs::try_exists(&path).await.unwrap_or(false) {
-                config_builder = config_builder.add_async_source(AsyncFileSource {
-                    format: FileFormat::Toml,
-                    file: path,
-                })
+        if let Some(path) = path {
+            if tokio::fs::try_exists(path).await.unwrap_or(false) {
+                config_builder =
+                    config_builder.add_async_source(AsyncFileSource::new(path.to_path_buf()))
             } else {
                 bail!("{} does not exist", path.display());
             }
         }
 
-        let cfg = config_builder
+        let prefix = env::var(ENV_PREFIX_VAR).unwrap_or_else(|_| ENV_PREFIX.to_string());
+        let separator = env::var(LIST_SEPARATOR_VAR).unwrap_or_else(|_| LIST_SEPARATOR.to_string());
+        ensure!(!prefix.is_empty(), "{} must not be empty", ENV_PREFIX_VAR);
+        ensure!(
+            !separator.is_empty(),
+            "{} must not be empty",
+            LIST_SEPARATOR_VAR
+        );
+
+        let mut cfg = config_builder
             .add_source(
-                Environment::with_prefix("BEERBOT")
-                    .list_separator("¬")
+                Environment::with_prefix(&prefix)
+                    .list_separator(&separator)
                     .try_parsing(true)
                     .with_list_parse_key("messages")
                     .with_list_parse_key("crons")
-                    .with_list_parse_key("gif_searches"),
+                    .with_list_parse_key("gif_searches")
+                    .with_list_parse_key("gif_providers")
+                    .with_list_parse_key("admin_users")
+                    .with_list_parse_key("command_channels")
+                    .with_list_parse_key("auto_reactions")
+                    .with_list_parse_key("decoration_emoji")
+                    .with_list_parse_key("on_post_command"),
             )
             .build()
             .await
             .with_context(|| "Failed to load config")?;
+        if cfg
+            .clone()
+            .try_deserialize::<Map<String, Value>>()
+            .is_ok_and(|values| values.is_empty())
+        {
+            let file = default_path.map_or_else(
+                || "a config file".to_string(),
+                |path| path.display().to_string(),
+            );
+            bail!(
+                "No config found. Create {} or pass its path, or set at least {}",
+                file,
+                required_env_vars(&prefix).join(", ")
+            );
+        }
+
+        let mut file_values = Vec::new();
+        for key in FILE_KEYS {
+            if let Ok(path) = cfg.get_string(&format!("{}_file", key)) {
+                let value = tokio::fs::read_to_string(&path)
+                    .await
+                    .with_context(|| format!("Failed to read {}_file {}", key, path))?;
+                file_values.push((key, value.trim().to_string()));
+            }
+        }
+        if !file_values.is_empty() {
+            let mut builder = config::Config::builder().add_source(cfg);
+            for (key, value) in file_values {
+                builder = builder.set_override(key, value)?;
+            }
+            cfg = builder.build().with_context(|| "Failed to load config")?;
+        }
+
+        let mut cfg: Config = cfg
+            .try_deserialize()
+            .with_context(|| "Failed to convert config")?;
+        cfg.expand_env_vars();
+        cfg.source = Source::Loaded {
+            path: path.map(Path::to_path_buf),
+        };
+        Ok(cfg)
+    }
+
+    /// Count every `@every` interval from `start`, so reloading doesn't shift them.
+    pub fn count_intervals_from(&mut self, start: DateTime<Local>) {
+        for job in &mut self.crons {
+            if let ScheduleKind::Interval { from, .. } = &mut job.schedule {
+                *from = start;
+            }
+        }
+    }
+
+    /// Replace `${VAR}` in messages and gif searches with the environment variable `VAR`.
+    fn expand_env_vars(&mut self) {
+        let unset = self.unset_env_vars;
+        self.message_prefix = expand_env_vars(&self.message_prefix, unset);
+        self.message_suffix = expand_env_vars(&self.message_suffix, unset);
+        for message in &mut self.messages {
+            message.text = expand_env_vars(&message.text, unset);
+            if let Some(blocks) = &message.blocks {
+                message.blocks = Some(expand_env_vars(blocks, unset));
+            }
+        }
+
+        #[cfg(feature = "giphy")]
+        for search in &mut self.gif_searches {
+            search.tag = expand_env_vars(&search.tag, unset);
+        }
+    }
+}
+
+impl Config {
+    /// Check the config for mistakes which would otherwise only surface when a cron fires.
+    pub fn validate(&self) -> Result<()> {
+        #[cfg(feature = "webhook")]
+        let has_webhook = self.webhook_url.is_some();
+        #[cfg(not(feature = "webhook"))]
+        let has_webhook = false;
+        #[cfg(feature = "discord")]
+        let has_webhook = has_webhook || self.platform == Platform::Discord;
+        ensure!(
+            !self.token.token_value.0.is_empty() || has_webhook,
+            "token must not be empty"
+        );
+
+        #[cfg(feature = "commands")]
+        ensure!(
+            self.socket_token
+                .as_ref()
+                .is_none_or(|t| !t.token_value.0.is_empty()),
+            "socket_token must not be empty"
+        );
+
+        for (idx, workspace) in self.workspaces.iter().enumerate() {
+            ensure!(
+                !workspace.token.token_value.0.is_empty(),
+                "workspaces[{}].token must not be empty",
+                idx
+            );
+
+            #[cfg(feature = "commands")]
+            ensure!(
+                workspace
+                    .socket_token
+                    .as_ref()
+                    .is_none_or(|t| !t.token_value.0.is_empty()),
+                "workspaces[{}].socket_token must not be empty",
+                idx
+            );
+        }
+
+        #[cfg(feature = "giphy")]
+        {
+            let providers = self.gif_providers();
+            if providers.contains(&GifProviderKind::Giphy) {
+                ensure!(
+                    !self.giphy_token.is_empty(),
+                    "giphy_token must not be empty"
+                );
+                // Giphy's API keys are only letters and numbers, so anything else is a copying mistake
+                ensure!(
+                    self.giphy_token.chars().all(|c| c.is_ascii_alphanumeric()),
+                    "giphy_token must only have letters and numbers, check it's a Giphy API key"
+                );
+            }
+            ensure!(
+                !providers.contains(&GifProviderKind::Tenor) || !self.tenor_token.is_empty(),
+                "tenor_token must not be empty"
+            );
+            ensure!(
+                self.gif_searches.iter().any(|s| s.weight > 0),
+                "gif_searches must have at least one search with a non-zero weight"
+            );
+            ensure!(
+                GIPHY_RATINGS.contains(&self.giphy_rating.as_str()),
+                "giphy_rating must be one of {}, not '{}'",
+                GIPHY_RATINGS.join(", "),
+                self.giphy_rating
+            );
+            ensure!(
+                (0.0..=1.0).contains(&self.giphy_cache_refresh),
+                "giphy_cache_refresh must be between 0 and 1"
+            );
+            ensure!(
+                self.giphy_base_url.scheme() == "https",
+                "giphy_base_url must be a https URL, not '{}'",
+                self.giphy_base_url
+            );
+            if let Some(attribution) = &self.giphy_attribution {
+                ensure!(
+                    attribution.to_uppercase().contains("GIPHY"),
+                    "giphy_attribution must mention GIPHY, not '{}'",
+                    attribution
+                );
+            }
+        }
+
+        #[cfg(feature = "webhook")]
+        if let Some(url) = &self.webhook_url {
+            ensure!(
+                url.scheme() == "https",
+                "webhook_url must be a https URL, not '{}'",
+                url
+            );
+        }
+
+        #[cfg(feature = "discord")]
+        if self.platform == Platform::Discord {
+            let Some(url) = &self.discord_webhook_url else {
+                bail!("discord_webhook_url must be set when platform is discord");
+            };
+            ensure!(
+                url.scheme() == "https",
+                "discord_webhook_url must be a https URL, not '{}'",
+                url
+            );
+            ensure!(
+                self.workspaces.is_empty(),
+                "workspaces can't be used when platform is discord"
+            );
+        }
+
+        #[cfg(feature = "audit")]
+        if let Some(url) = &self.audit_webhook {
+            ensure!(
+                url.scheme() == "https",
+                "audit_webhook must be a https URL, not '{}'",
+                url
+            );
+        }
+
+        ensure!(
+            self.messages.iter().any(|m| m.weight > 0),
+            "messages must have at least one message with a non-zero weight"
+        );
+
+        for (idx, emoji) in self.decoration_emoji.iter().enumerate() {
+            ensure!(
+                !emoji.trim().is_empty(),
+                "decoration_emoji[{}] must not be empty",
+                idx
+            );
+        }
+
+        for (idx, message) in self.messages.iter().enumerate() {
+            match &message.blocks {
+                Some(blocks) => {
+                    serde_json::from_str::<Vec<SlackBlock>>(blocks).with_context(|| {
+                        format!("messages[{}].blocks is not valid Block Kit JSON", idx)
+                    })?;
+                }
+                None => ensure!(
+                    !message.text.is_empty(),
+                    "messages[{}] must have text or blocks",
+                    idx
+                ),
+            }
+        }
+
+        for (idx, job) in self.crons.iter().enumerate() {
+            // Otherwise finding when it next fires never finishes
+            let fires_on = |ordinal| match &job.schedule {
+                ScheduleKind::Cron(schedule) => schedule.days_of_week().includes(ordinal),
+                ScheduleKind::Interval { .. } => true,
+            };
+            ensure!(
+                (1..=7).any(|ordinal| fires_on(ordinal)
+                    && !job
+                        .skip_weekdays
+                        .iter()
+                        .any(|day| day.number_from_sunday() == ordinal)),
+                "crons[{}] = '{}' skips every weekday it fires on",
+                idx,
+                job.schedule
+            );
+            if let Some(group) = &job.user_group {
+                ensure!(
+                    group.0.starts_with('S')
+                        && group.0.len() > 1
+                        && group
+                            .0
+                            .chars()
+                            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()),
+                    "crons[{}].user_group must be a user group ID like 'S0614TZR7', not '{}'",
+                    idx,
+                    group
+                );
+            }
+            if let Some(username) = &job.username {
+                ensure!(
+                    !username.trim().is_empty(),
+                    "crons[{}].username must not be empty",
+                    idx
+                );
+            }
+            if let Some(emoji) = &job.icon_emoji {
+                ensure!(
+                    is_emoji(emoji),
+                    "crons[{}].icon_emoji must be an emoji name like ':beers:', not '{}'",
+                    idx,
+                    emoji
+                );
+            }
+            ensure!(
+                !job.enabled || job.next_occurrence(Local::now()).is_some(),
+                "crons[{}] = '{}' never fires",
+                idx,
+                job.schedule
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Gif providers to try in order, falling back to only `gif_provider`.
+    #[cfg(feature = "giphy")]
+    pub fn gif_providers(&self) -> Vec<GifProviderKind> {
+        if self.gif_providers.is_empty() {
+            vec![self.gif_provider]
+        } else {
+            self.gif_providers.clone()
+        }
+    }
+
+    /// Whether Slack should unfurl links in announcements, so gifs posted as links are shown.
+    #[cfg(feature = "giphy")]
+    pub fn unfurl(&self) -> Option<bool> {
+        (self.giphy_render == GiphyRender::Unfurl).then_some(true)
+    }
+
+    #[cfg(not(feature = "giphy"))]
+    #[inline]
+    pub fn unfurl(&self) -> Option<bool> {
+        None
+    }
+
+    /// Every workspace to announce in, starting with the one the top-level tokens are for.
+    pub fn all_workspaces(&self) -> Vec<Workspace> {
+        std::iter::once(Workspace {
+            token: self.token.clone(),
+            #[cfg(feature = "commands")]
+            socket_token: self.socket_token.clone(),
+            channel_id: self.channel_id.clone(),
+        })
+        .chain(self.workspaces.iter().cloned())
+        .collect()
+    }
+
+    /// The soonest time after `now` any cron will next announce.
+    pub fn next_drink(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        self.crons
+            .iter()
+            .filter_map(|job| job.next_occurrence(now))
+            .min()
+    }
+
+    /// Index of the cron which will next announce after `now`, if any will.
+    pub fn next_cron(&self, now: DateTime<Local>) -> Option<usize> {
+        self.crons
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, job)| Some((job.next_occurrence(now)?, idx)))
+            .min()
+            .map(|(_, idx)| idx)
+    }
+}
+
+impl CronJob {
+    /// The channels this cron posts to, falling back to the top-level `channel_id`.
+    pub fn channel_ids<'a>(&'a self, cfg: &'a Config) -> Vec<&'a SlackChannelId> {
+        let channels = self
+            .channel_id
+            .iter()
+            .chain(&self.channel_ids)
+            .collect::<Vec<_>>();
+        if channels.is_empty() {
+            vec![&cfg.channel_id]
+        } else {
+            channels
+        }
+    }
+
+    /// Whether `dt` is on a date or weekday this cron skips.
+    pub fn is_skipped(&self, dt: &DateTime<Local>) -> bool {
+        let date = match self.timezone {
+            Some(tz) => dt.with_timezone(&tz).date_naive(),
+            None => dt.date_naive(),
+        };
+        self.skip_dates.contains(&date) || self.skip_weekdays.contains(&date.weekday())
+    }
+
+    /// The time of day of `dt` in this cron's timezone.
+    pub fn time_of_day(&self, dt: &DateTime<Local>) -> NaiveTime {
+        match self.timezone {
+            Some(tz) => dt.with_timezone(&tz).time(),
+            None => dt.time(),
+        }
+    }
+
+    /// Today's date in this cron's timezone.
+    pub fn today(&self) -> NaiveDate {
+        self.date(Local::now())
+    }
+
+    /// The date of `at` in this cron's timezone.
+    pub fn date(&self, at: DateTime<Local>) -> NaiveDate {
+        match self.timezone {
+            Some(tz) => at.with_timezone(&tz).date_naive(),
+            None => at.date_naive(),
+        }
+    }
+
+    /// When this cron will next announce after `now`, the same as used when posting, so commands
+    /// agree with it.
+    pub fn next_occurrence(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        self.upcoming(now).next()
+    }
+
+    /// Times after `now` this cron will announce, calculated in its timezone. Never if disabled.
+    pub fn upcoming(
+        &self,
+        now: DateTime<Local>,
+    ) -> Box<dyn Iterator<Item = DateTime<Local>> + Send + '_> {
+        if !self.enabled {
+            return Box::new(std::iter::empty());
+        }
+        Box::new(self.scheduled(now).filter(|dt| !self.is_skipped(dt)))
+    }
+
+    /// Times after `now` this cron's schedule fires, including skipped dates.
+    pub fn scheduled(
+        &self,
+        now: DateTime<Local>,
+    ) -> Box<dyn Iterator<Item = DateTime<Local>> + Send + '_> {
+        match (&self.schedule, self.timezone) {
+            (ScheduleKind::Cron(schedule), Some(tz)) => Box::new(
+                schedule
+                    .after(&now.with_timezone(&tz))
+                    .map(|dt| dt.with_timezone(&Local)),
+            ),
+            (ScheduleKind::Cron(schedule), None) => Box::new(schedule.after(&now)),
+            // Intervals are the same in every timezone
+            (ScheduleKind::Interval { every, from }, _) => {
+                let every = every.as_millis() as i64;
+                let from = *from;
+                let elapsed = (now - from).num_milliseconds().max(0);
+                Box::new(
+                    (elapsed / every + 1..)
+                        .map(move |n| from + chrono::Duration::milliseconds(every * n)),
+                )
+            }
+        }
+    }
+}
 
-        cfg.try_deserialize()
-            .with_context(|| "Failed to convert config")
+impl QuietHours {
+    /// Whether `time` is within the window, including `start` but not `end`.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl Notify {
+    /// The Slack mention for this, if any.
+    pub fn mention(&self) -> Option<&'static str> {
+        match self {
+            Notify::None => None,
+            Notify::Here => Some("<!here>"),
+            Notify::Channel => Some("<!channel>"),
+        }
+    }
+}
+
+impl Display for ScheduleKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduleKind::Cron(schedule) => write!(f, "{}", schedule),
+            ScheduleKind::Interval { every, .. } => {
+                f.write_str("@every ")?;
+                let mut rest = every.as_secs();
+                for (unit, len) in INTERVAL_UNITS {
+                    if rest >= len {
+                        write!(f, "{}{}", rest / len, unit)?;
+                        rest %= len;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for CronJob {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "{}: ", name)?;
+        }
+        write!(f, "{}", self.schedule)?;
+        if let Some(tz) = &self.timezone {
+            write!(f, " ({})", tz)?;
+        }
+        let channels = self
+            .channel_id
+            .iter()
+            .chain(&self.channel_ids)
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        if !channels.is_empty() {
+            write!(f, " -> {}", channels.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<MessageEntry> for Message {
+    fn from(value: MessageEntry) -> Self {
+        match value {
+            MessageEntry::Bare(text) => text.into(),
+            MessageEntry::Weighted {
+                text,
+                weight,
+                blocks,
+                weekdays,
+            } => Message {
+                text,
+                weight,
+                blocks,
+                weekdays,
+            },
+        }
+    }
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Message {
+            text,
+            weight: default_weight(),
+            blocks: None,
+            weekdays: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "giphy")]
+impl From<GifSearchEntry> for GifSearch {
+    fn from(value: GifSearchEntry) -> Self {
+        match value {
+            GifSearchEntry::Bare(tag) => GifSearch {
+                tag,
+                weight: default_weight(),
+            },
+            GifSearchEntry::Weighted { tag, weight } => GifSearch { tag, weight },
+        }
+    }
+}
+
+#[cfg(feature = "giphy")]
+impl Display for GifSearch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.tag)?;
+        if self.weight != default_weight() {
+            write!(f, " (weight {})", self.weight)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)?;
+        if self.blocks.is_some() {
+            f.write_str(" (blocks)")?;
+        }
+        if !self.weekdays.is_empty() {
+            let weekdays = self
+                .weekdays
+                .iter()
+                .map(|day| day.to_string())
+                .collect::<Vec<_>>();
+            write!(f, " (on {})", weekdays.join(", "))?;
+        }
+        if self.weight != default_weight() {
+            write!(f, " (weight {})", self.weight)?;
+        }
+        Ok(())
     }
 }
 
 impl Display for Config {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!(
-            "{{ token: (len:{}), crons: [{}], messages: [{}], log: \"{}\" ",
+            "{{ token: (len:{}), workspaces: [{}], crons: [{}], messages: [{}], log: \"{}\", log_format: {:?}, dry_run: {}, post_retries: {}, message_history: {}, jitter_seconds: {} ",
             self.token.token_value.0.len(),
+            self.workspaces
+                .iter()
+                .map(|w| w.channel_id.to_string())
+                .collect::<Vec<String>>()
+                .join(", "),
             self.crons
                 .iter()
                 .map(|c| c.to_string())
                 .collect::<Vec<String>>()
                 .join(", "),
-            self.messages.join(", "),
-            self.log
+            self.messages
+                .iter()
+                .map(|m| m.to_string())
+                .collect::<Vec<String>>()
+                .join(", "),
+            self.log,
+            self.log_format,
+            self.dry_run,
+            self.post_retries,
+            self.message_history,
+            self.jitter_seconds
         ))?;
 
         #[cfg(feature = "commands")]
         {
             f.write_fmt(format_args!(
-                "socket_token: (len: {}) ",
-                self.socket_token.token_value.0.len(),
+                "socket_token: (len: {:?}) ",
+                self.socket_token.as_ref().map(|t| t.token_value.0.len()),
             ))?;
         }
 
         #[cfg(feature = "giphy")]
         {
             f.write_fmt(format_args!(
-                "gif_searches: [{}] ",
-                self.gif_searches.join(", ")
+                "gif_providers: {:?}, giphy_kind: {:?}, gif_searches: [{}] ",
+                self.gif_providers(),
+                self.giphy_kind,
+                self.gif_searches
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
             ))?;
         }
 
+        #[cfg(feature = "healthcheck")]
+        {
+            f.write_fmt(format_args!("health_addr: {} ", self.health_addr))?;
+        }
+
+        #[cfg(feature = "metrics")]
+        {
+            f.write_fmt(format_args!("metrics_addr: {} ", self.metrics_addr))?;
+        }
+
         f.write_str("}")?;
 
         Ok(())
     }
 }
 
+impl<P: AsRef<Path> + Debug> AsyncFileSource<FileFormat, P> {
+    /// Create a source for `file`, picking the format from its extension. Defaults to TOML.
+    fn new(file: P) -> Self {
+        let format = match file.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => FileFormat::Yaml,
+            Some("json") => FileFormat::Json,
+            _ => FileFormat::Toml,
+        };
+        AsyncFileSource { format, file }
+    }
+}
+
 // Remove async_trait when config drops it
 #[async_trait]
 impl<F: Format + Send + Sync + Debug, P: AsRef<Path> + Debug + Sync> AsyncSource
     for AsyncFileSource<F, P>
 {
     async fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
-        let content =
-            tokio::fs::read_to_string(&self.file)
-                .await
-                .map_err(|e| ConfigError::FileParse {
-                    uri: Some(self.file.as_ref().display().to_string()),
-                    cause: Box::new(e),
-                })?;
+        let mut attempt = 1;
+        let content = loop {
+            match tokio::fs::read_to_string(&self.file).await {
+                Ok(content) => break content,
+                // A missing file won't turn up by waiting
+                Err(e) if e.kind() != ErrorKind::NotFound && attempt < FILE_READ_ATTEMPTS => {
+                    warn!(?e, file = ?self.file, attempt, "failed to read config file, retrying");
+                    attempt += 1;
+                    tokio::time::sleep(FILE_READ_RETRY_DELAY).await;
+                }
+                Err(e) => {
+                    return Err(ConfigError::FileParse {
+                        uri: Some(self.file.as_ref().display().to_string()),
+                        cause: Box::new(e),
+                    })
+                }
+            }
+        };
 
         let url = self.file.as_ref().display().to_string();
         self.format
@@ -182,9 +1314,394 @@ impl<'de> Visitor<'de> for SlackApiTokenVisitor {
     }
 }
 
+/// Environment variables, with `prefix`, which must be set when there's no config file.
+fn required_env_vars(prefix: &str) -> Vec<String> {
+    let mut keys = vec!["token", "channel_id", "crons", "messages"];
+    if cfg!(feature = "giphy") {
+        keys.extend(["giphy_token", "gif_searches"]);
+    }
+    if cfg!(feature = "healthcheck") {
+        keys.push("health_addr");
+    }
+    if cfg!(feature = "metrics") {
+        keys.push("metrics_addr");
+    }
+    keys.into_iter()
+        .map(|key| format!("{}_{}", prefix, key.to_uppercase()))
+        .collect()
+}
+
+/// Replace each `${VAR}` in `value` with the environment variable `VAR`.
+fn expand_env_vars(value: &str, unset: UnsetEnvVars) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let var = &rest[start..start + len + 1];
+        match (env::var(&var[2..var.len() - 1]), unset) {
+            (Ok(value), _) => out.push_str(&value),
+            (Err(_), UnsetEnvVars::Keep) => out.push_str(var),
+            (Err(_), UnsetEnvVars::Empty) => {}
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+
+    out
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_shutdown_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_post_retries() -> u32 {
+    3
+}
+
+#[cfg(feature = "giphy")]
+fn default_show_header() -> bool {
+    true
+}
+
+#[cfg(feature = "giphy")]
+fn default_giphy_base_url() -> Url {
+    url_macro::url!("https://api.giphy.com/v1/")
+}
+
+#[cfg(feature = "giphy")]
+fn default_giphy_rating() -> String {
+    "pg".to_string()
+}
+
+#[cfg(feature = "giphy")]
+fn default_giphy_cache_refresh() -> f64 {
+    0.5
+}
+
+fn default_message_history() -> usize {
+    1
+}
+
+fn deserialize_crons<'de, D>(deserializer: D) -> Result<Vec<CronJob>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<CronEntry>::deserialize(deserializer)?
+        .into_iter()
+        .enumerate()
+        .map(|(idx, entry)| match entry {
+            CronEntry::Bare(cron) => Ok(CronJob {
+                schedule: parse_schedule(idx, &cron).map_err(D::Error::custom)?,
+                name: None,
+                user_group: None,
+                username: None,
+                icon_emoji: None,
+                enabled: true,
+                channel_id: None,
+                channel_ids: Vec::new(),
+                timezone: None,
+                notify: Notify::None,
+                skip_dates: Vec::new(),
+                skip_weekdays: Vec::new(),
+                thread_ts: None,
+            }),
+            CronEntry::Job(job) => Ok(*job),
+            CronEntry::Invalid {
+                schedule: Some(cron),
+            } => Err(D::Error::custom(match parse_schedule(idx, &cron) {
+                Ok(_) => format!("crons[{}] = '{}' has an invalid option", idx, cron),
+                Err(e) => e,
+            })),
+            CronEntry::Invalid { schedule: None } => Err(D::Error::custom(format!(
+                "crons[{}] must have a schedule",
+                idx
+            ))),
+        })
+        .collect()
+}
+
+/// Whether `emoji` is an emoji name between colons, e.g. `:beers:`.
+fn is_emoji(emoji: &str) -> bool {
+    emoji
+        .strip_prefix(':')
+        .and_then(|emoji| emoji.strip_suffix(':'))
+        .is_some_and(|name| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_+-'".contains(c))
+        })
+}
+
+/// Parse the `idx`th cron, saying which it is if it's invalid.
+fn parse_schedule(idx: usize, cron: &str) -> Result<ScheduleKind, String> {
+    parse_cron(cron).map_err(|e| format!("crons[{}] = '{}' is not a valid cron: {}", idx, cron, e))
+}
+
+/// Parse an interval like `90m` or `1h30m`, in days, hours, minutes and seconds.
+fn parse_interval(interval: &str) -> Result<Duration, String> {
+    let mut secs = 0u64;
+    let mut number = String::new();
+    for c in interval.chars().filter(|c| !c.is_whitespace()) {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let Some((_, len)) = INTERVAL_UNITS.iter().find(|(unit, _)| unit.starts_with(c)) else {
+            return Err(format!("unknown unit '{}', expected d, h, m or s", c));
+        };
+        let n = number
+            .parse::<u64>()
+            .map_err(|_| format!("expected a number before '{}'", c))?;
+        secs = n
+            .checked_mul(*len)
+            .and_then(|n| secs.checked_add(n))
+            .ok_or_else(|| "interval is too long".to_string())?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("'{}' needs a unit of d, h, m or s", number));
+    }
+    if secs == 0 {
+        return Err("interval must be at least 1s".to_string());
+    }
+    let interval = Duration::from_secs(secs);
+    if interval > MAX_INTERVAL {
+        return Err(format!(
+            "interval must be at most {}d",
+            MAX_INTERVAL.as_secs() / INTERVAL_UNITS[0].1
+        ));
+    }
+    Ok(interval)
+}
+
+/// Parse `cron`, with a seconds column prepended if it's a 5 field crontab expression, or an
+/// `@every` interval.
+fn parse_cron(cron: &str) -> Result<ScheduleKind, String> {
+    if let Some(interval) = cron.trim().strip_prefix("@every") {
+        return parse_interval(interval).map(|every| ScheduleKind::Interval {
+            every,
+            from: Local::now(),
+        });
+    }
+
+    let cron = match cron.split_whitespace().count() {
+        // Shorthands like @daily have a single field
+        _ if cron.trim_start().starts_with('@') => Cow::Borrowed(cron),
+        5 => Cow::Owned(format!("0 {}", cron)),
+        6 | 7 => Cow::Borrowed(cron),
+        fields => {
+            return Err(format!(
+                "expected 6 or 7 fields (sec min hour day month weekday [year]), or 5 like crontab, not {}",
+                fields
+            ))
+        }
+    };
+    cron.parse::<Schedule>()
+        .map(|schedule| ScheduleKind::Cron(Box::new(schedule)))
+        .map_err(|e| e.to_string())
+}
+
+fn deserialize_schedule<'de, D>(deserializer: D) -> Result<ScheduleKind, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    parse_cron(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+/// Parse a message timestamp, e.g. `1712345678.123456`, or take it from a permalink, which ends in
+/// the timestamp without its dot, e.g. `https://x.slack.com/archives/C0123/p1712345678123456`.
+fn parse_thread_ts(thread: &str) -> Option<SlackTs> {
+    let ts = match thread.rsplit_once("/p") {
+        Some((_, rest)) => {
+            let digits = rest.split('?').next().unwrap_or_default();
+            if digits.len() <= 6 || !digits.is_char_boundary(digits.len() - 6) {
+                return None;
+            }
+            let (secs, micros) = digits.split_at(digits.len() - 6);
+            format!("{}.{}", secs, micros)
+        }
+        None => thread.trim().to_string(),
+    };
+    let (secs, micros) = ts.split_once('.')?;
+    (!secs.is_empty()
+        && micros.len() == 6
+        && secs
+            .chars()
+            .chain(micros.chars())
+            .all(|c| c.is_ascii_digit()))
+    .then_some(SlackTs(ts))
+}
+
+fn deserialize_thread_ts<'de, D>(deserializer: D) -> Result<Option<SlackTs>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let thread = String::deserialize(deserializer)?;
+    parse_thread_ts(&thread).map(Some).ok_or_else(|| {
+        D::Error::custom(format!(
+            "'{}' is not a message timestamp or permalink",
+            thread
+        ))
+    })
+}
+
 fn deserialize_token<'de, D>(deserializer: D) -> Result<SlackApiToken, D::Error>
 where
     D: Deserializer<'de>,
 {
     deserializer.deserialize_string(SlackApiTokenVisitor)
 }
+
+#[cfg(any(feature = "webhook", feature = "discord"))]
+fn empty_token() -> SlackApiToken {
+    SlackApiToken::new(SlackApiTokenValue(String::new()))
+}
+
+#[cfg(feature = "commands")]
+fn deserialize_opt_token<'de, D>(deserializer: D) -> Result<Option<SlackApiToken>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_token(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    /// An enabled cron for `schedule` in `timezone`, with nothing else set.
+    fn job(schedule: &str, timezone: Option<Tz>) -> CronJob {
+        CronJob {
+            schedule: parse_cron(schedule).unwrap(),
+            name: None,
+            username: None,
+            icon_emoji: None,
+            enabled: true,
+            channel_id: None,
+            channel_ids: Vec::new(),
+            timezone,
+            notify: Notify::None,
+            user_group: None,
+            skip_dates: Vec::new(),
+            skip_weekdays: Vec::new(),
+            thread_ts: None,
+        }
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Local> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0)
+            .unwrap()
+            .with_timezone(&Local)
+    }
+
+    #[test]
+    fn nine_am_in_a_timezone_offset_from_the_host() {
+        // Two zones, so at least one is offset from the host whatever it's in
+        for (tz, expected) in [
+            (Tz::Asia__Tokyo, utc(2024, 6, 8, 0, 0)),
+            (Tz::America__New_York, utc(2024, 6, 7, 13, 0)),
+        ] {
+            let job = job("0 9 * * *", Some(tz));
+            let next = job.next_occurrence(utc(2024, 6, 7, 12, 0)).unwrap();
+
+            assert_eq!(next, expected, "in {}", tz);
+            assert_eq!(
+                job.time_of_day(&next),
+                NaiveTime::from_hms_opt(9, 0, 0).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn next_occurrence_across_clocks_going_forward() {
+        let job = job("0 9 * * *", Some(Tz::Europe__London));
+        let now = utc(2024, 3, 30, 12, 0);
+
+        assert_eq!(job.next_occurrence(now), Some(utc(2024, 3, 31, 8, 0)));
+        assert_eq!(
+            job.upcoming(now).take(2).collect::<Vec<_>>(),
+            [utc(2024, 3, 31, 8, 0), utc(2024, 4, 1, 8, 0)]
+        );
+    }
+
+    #[test]
+    fn next_occurrence_across_clocks_going_back() {
+        let job = job("0 9 * * *", Some(Tz::Europe__London));
+        let now = utc(2024, 10, 26, 12, 0);
+
+        assert_eq!(
+            job.upcoming(now).take(2).collect::<Vec<_>>(),
+            [utc(2024, 10, 27, 9, 0), utc(2024, 10, 28, 9, 0)]
+        );
+    }
+
+    #[test]
+    fn skipped_dates_are_scheduled_but_not_upcoming() {
+        let mut job = job("0 9 * * *", Some(Tz::Europe__London));
+        job.skip_dates = vec![NaiveDate::from_ymd_opt(2024, 4, 1).unwrap()];
+        let now = utc(2024, 3, 31, 12, 0);
+
+        assert_eq!(job.next_occurrence(now), Some(utc(2024, 4, 2, 8, 0)));
+        assert_eq!(job.scheduled(now).next(), Some(utc(2024, 4, 1, 8, 0)));
+    }
+
+    #[test]
+    fn skipped_weekdays_are_in_the_crons_timezone() {
+        // 08:00 on Saturday in Tokyo is still Friday in UTC
+        let mut job = job("0 8 * * *", Some(Tz::Asia__Tokyo));
+        job.skip_weekdays = vec![Weekday::Sat];
+        let now = utc(2024, 6, 7, 12, 0);
+
+        assert_eq!(job.next_occurrence(now), Some(utc(2024, 6, 8, 23, 0)));
+    }
+
+    #[test]
+    fn disabled_crons_never_occur() {
+        let mut job = job("0 9 * * *", None);
+        job.enabled = false;
+
+        assert_eq!(job.next_occurrence(utc(2024, 6, 7, 12, 0)), None);
+    }
+
+    #[test]
+    fn intervals_are_at_most_a_year() {
+        assert_eq!(parse_interval("365d"), Ok(MAX_INTERVAL));
+        assert!(parse_interval("365d 1s").is_err());
+        assert!(parse_interval("999999999999d").is_err());
+        assert!(parse_cron("@every 999999999999d").is_err());
+    }
+
+    #[test]
+    fn reloaded_intervals_count_from_the_same_start() {
+        let start = utc(2024, 6, 7, 12, 0);
+        let mut cfg: Config = serde_json::from_value(serde_json::json!({
+            "token": "xoxb-test",
+            "channel_id": "C0123",
+            "crons": ["@every 90m", "0 9 * * *"],
+            "messages": ["Beer time!"],
+            "health_addr": "127.0.0.1:0",
+            "metrics_addr": "127.0.0.1:0",
+        }))
+        .unwrap();
+        cfg.count_intervals_from(start);
+
+        assert_eq!(
+            cfg.crons[0].next_occurrence(start + chrono::Duration::minutes(100)),
+            Some(utc(2024, 6, 7, 15, 0))
+        );
+    }
+}
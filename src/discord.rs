@@ -0,0 +1,190 @@
+use crate::message::HttpClient;
+use crate::post::Poster;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use slack_morphism::SlackMessageContent;
+use tracing::{instrument, trace};
+use url::Url;
+
+/// Posts to a Discord webhook, with only the message's text and images.
+pub struct Discord<'a> {
+    http: &'a HttpClient,
+    url: &'a Url,
+}
+
+/// Body of a Discord webhook request.
+#[derive(Debug, Serialize)]
+struct Payload {
+    content: String,
+    embeds: Vec<Embed>,
+}
+
+#[derive(Debug, Serialize)]
+struct Embed {
+    image: EmbedImage,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    footer: Option<EmbedFooter>,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedImage {
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedFooter {
+    text: String,
+}
+
+impl<'a> Discord<'a> {
+    pub fn new(http: &'a HttpClient, url: &'a Url) -> Discord<'a> {
+        Discord { http, url }
+    }
+}
+
+#[async_trait]
+impl Poster for Discord<'_> {
+    #[instrument(skip_all)]
+    async fn post(&self, content: &SlackMessageContent) -> Result<()> {
+        let payload = Payload::from_slack(content)?;
+        trace!(?payload, "posting to Discord");
+        self.http
+            .post(self.url.clone())
+            .json(&payload)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+impl Payload {
+    /// The Discord equivalent of `content`: its text, after any sections it doesn't already
+    /// include such as mentions, with Slack's mentions swapped for Discord's, and its images as
+    /// embeds, captioned with their titles, e.g. the gif attribution. Gifs sent as links to unfurl
+    /// are embedded too, captioned with the context block after them.
+    fn from_slack(content: &SlackMessageContent) -> Result<Payload> {
+        let text = content.text.as_deref().unwrap_or_default();
+
+        // Read from the JSON, which is the same whichever way slack-morphism models blocks
+        let blocks = serde_json::to_value(&content.blocks)?;
+        let blocks = blocks.as_array().map(Vec::as_slice).unwrap_or_default();
+        let mut sections = Vec::new();
+        let mut embeds = Vec::new();
+        for (idx, block) in blocks.iter().enumerate() {
+            match block["type"].as_str() {
+                Some("image") => embeds.extend(block["image_url"].as_str().map(|url| Embed {
+                    image: EmbedImage {
+                        url: url.to_string(),
+                    },
+                    footer: block["title"]["text"].as_str().map(|text| EmbedFooter {
+                        text: text.to_string(),
+                    }),
+                })),
+                Some("section") => {
+                    let Some(section) = block["text"]["text"].as_str() else {
+                        continue;
+                    };
+                    match unfurled(section) {
+                        Some(url) => embeds.push(Embed {
+                            image: EmbedImage {
+                                url: url.to_string(),
+                            },
+                            footer: blocks
+                                .get(idx + 1)
+                                .filter(|next| next["type"] == "context")
+                                .and_then(|next| next["elements"][0]["text"].as_str())
+                                .map(|text| EmbedFooter {
+                                    text: text.to_string(),
+                                }),
+                        }),
+                        None if !text.contains(section) => sections.push(section),
+                        None => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let text = sections
+            .into_iter()
+            .chain((!text.is_empty()).then_some(text))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .replace("<!here>", "@here")
+            .replace("<!channel>", "@everyone");
+
+        Ok(Payload {
+            content: text,
+            embeds,
+        })
+    }
+}
+
+/// The URL of a section that's only a link, as gifs are sent to be unfurled.
+fn unfurled(section: &str) -> Option<&str> {
+    let (url, _) = section
+        .strip_prefix('<')?
+        .strip_suffix('>')?
+        .split_once('|')?;
+    url.starts_with("https://").then_some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slack_morphism::blocks::{
+        SlackBlock, SlackBlockMarkDownText, SlackContextBlock, SlackContextBlockElement,
+        SlackSectionBlock,
+    };
+
+    fn section(text: &str) -> SlackBlock {
+        SlackBlock::Section(
+            SlackSectionBlock::new().with_text(SlackBlockMarkDownText::new(text.into()).into()),
+        )
+    }
+
+    #[test]
+    fn mentions_in_blocks_are_sent() {
+        let content = SlackMessageContent::new()
+            .with_text("Beer time!".into())
+            .with_blocks(vec![
+                section("<!here> <!subteam^S123>"),
+                section("Beer time!"),
+            ]);
+
+        let payload = Payload::from_slack(&content).unwrap();
+
+        assert_eq!(payload.content, "@here <!subteam^S123>\nBeer time!");
+    }
+
+    #[test]
+    fn unfurled_gifs_are_embedded() {
+        let content = SlackMessageContent::new()
+            .with_text("Beer time! (cheers)".into())
+            .with_blocks(vec![
+                section("<https://media.giphy.com/beer.gif|cheers>"),
+                SlackBlock::Context(SlackContextBlock::new(vec![
+                    SlackContextBlockElement::MarkDown(SlackBlockMarkDownText::new(
+                        "Powered By GIPHY".into(),
+                    )),
+                ])),
+            ]);
+
+        let payload = Payload::from_slack(&content).unwrap();
+
+        assert_eq!(payload.content, "Beer time! (cheers)");
+        assert_eq!(payload.embeds.len(), 1);
+        assert_eq!(
+            payload.embeds[0].image.url,
+            "https://media.giphy.com/beer.gif"
+        );
+        assert_eq!(
+            payload.embeds[0].footer.as_ref().unwrap().text,
+            "Powered By GIPHY"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+#![cfg_attr(not(feature = "exec"), allow(unused_imports))]
+use crate::config::Config;
+use slack_morphism::{SlackChannelId, SlackMessageContent};
+use tracing::{debug, instrument, warn};
+
+/// Run `on_post_command` in the background, if it's set, with the announcement in its environment.
+/// Never waits for it, so a slow command can't hold up the next announcement.
+#[cfg(feature = "exec")]
+#[instrument(skip_all, fields(%channel))]
+pub fn on_post(cfg: &Config, channel: &SlackChannelId, content: &SlackMessageContent) {
+    let Some((program, args)) = cfg.on_post_command.split_first() else {
+        return;
+    };
+
+    let mut command = tokio::process::Command::new(program);
+    command
+        .args(args)
+        .env(
+            "BEERBOT_POSTED_TEXT",
+            content.text.as_deref().unwrap_or_default(),
+        )
+        .env("BEERBOT_POSTED_CHANNEL", channel.to_string());
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            warn!(?e, program, "failed to run on_post_command");
+            return;
+        }
+    };
+
+    let program = program.clone();
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) if status.success() => debug!(program, "on_post_command finished"),
+            Ok(status) => warn!(program, %status, "on_post_command failed"),
+            Err(e) => warn!(?e, program, "failed to wait for on_post_command"),
+        }
+    });
+}
+
+#[cfg(not(feature = "exec"))]
+#[inline]
+pub fn on_post(_: &Config, _: &SlackChannelId, _: &SlackMessageContent) {}
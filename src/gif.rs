@@ -0,0 +1,33 @@
+use crate::giphy::GiphyError;
+use async_trait::async_trait;
+use serde::Deserialize;
+
+#[derive(Debug, Clone)]
+pub struct Gif {
+    pub url: String,
+    pub alt_text: String,
+
+    /// Page to view the gif on, on the provider's site.
+    pub page_url: String,
+
+    /// Pixel dimensions of the image, if the provider says.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// A source of random gifs.
+#[async_trait]
+pub trait GifProvider: Send + Sync {
+    async fn random(&self, search: &str) -> Result<Gif, GiphyError>;
+
+    /// Attribution text required when showing a gif from this provider.
+    fn attribution(&self) -> &str;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GifProviderKind {
+    #[default]
+    Giphy,
+    Tenor,
+}
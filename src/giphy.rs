@@ -1,70 +1,585 @@
-use anyhow::Result;
-use reqwest::Client;
+use crate::gif::{Gif, GifProvider};
+use async_trait::async_trait;
+use rand::prelude::IteratorRandom;
+use rand::Rng;
+use reqwest::{Client, RequestBuilder, Response as HttpResponse, StatusCode};
 use serde::Deserialize;
+use serde_with::serde_as;
+use serde_with::DisplayFromStr;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::{debug, info, trace, warn};
 use url::Url;
 use url_macro::url;
 
+/// A request for a rating and search, shared by everyone waiting on it.
+type InFlight = Arc<OnceCell<Result<Gif, GiphyError>>>;
+
+/// Requests shared by every [`Giphy`] given them.
+#[derive(Debug, Default)]
+pub struct Requests {
+    /// Requests currently being made, so crons firing at the same time make one request per
+    /// search.
+    in_flight: Mutex<BTreeMap<(Url, String, String), InFlight>>,
+
+    /// When each API's rate limit resets, so none ask until then.
+    cooldowns: Mutex<BTreeMap<Url, Instant>>,
+}
+
+/// How long to stop asking once rate limited, if Giphy doesn't say.
+const RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(15 * 60);
+
+/// Attribution shown under gifs, as Giphy requires.
+pub const ATTRIBUTION: &str = "Powered By GIPHY";
+
+/// Rating which leaves gifs unfiltered.
+pub const NO_RATING: &str = "none";
+
+/// Times to ask for a gif for a search, as Giphy sometimes sends one without an image.
+const FETCH_ATTEMPTS: usize = 3;
+
+/// Why a gif couldn't be fetched. Also used for Tenor, which fails in the same ways.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum GiphyError {
+    #[error("rate limited")]
+    RateLimited,
+
+    /// The token was rejected, with the status it was rejected with, as opposed to any other
+    /// failed request.
+    #[error("token rejected with {0}, check the token is a valid API key")]
+    Unauthorized(StatusCode),
+
+    #[error("no gifs found for {0}")]
+    NoResults(String),
+
+    #[error("request failed: {0}")]
+    Http(String),
+
+    #[error("invalid response: {0}")]
+    Deserialize(String),
+}
+
+/// What Giphy should pick randomly from.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GiphyKind {
+    #[default]
+    Gif,
+
+    /// Gifs with transparent backgrounds.
+    Sticker,
+}
+
 pub struct Giphy<'a> {
     client: Client,
     token: &'a str,
-    random_url: Url,
+    base_url: Url,
+    kind: GiphyKind,
+    rating: &'a str,
+    attribution: &'a str,
+
+    /// Bounds on the image to send, if smaller renditions should be preferred.
+    limits: Limits,
+
+    /// Max number of gifs cached per search. 0 disables the cache.
+    cache_size: usize,
+
+    /// Chance of fetching a new gif rather than using a cached one.
+    cache_refresh: f64,
+
+    cache: Mutex<HashMap<String, VecDeque<Gif>>>,
+
+    /// Requests in flight and rate limits, by this alone unless given shared ones.
+    requests: Arc<Requests>,
 }
 
-#[derive(Debug)]
-pub struct Gif {
-    pub url: String,
-    pub alt_text: String,
+/// Bounds on the rendition of a gif to send. Unset bounds don't limit it.
+#[derive(Debug, Default, Clone, Copy)]
+struct Limits {
+    max_bytes: Option<u64>,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
 }
 
 #[derive(Debug, Deserialize)]
 struct GifResponse {
     alt_text: String,
+
+    #[serde(default)]
+    url: String,
     images: Images,
 }
 
 #[derive(Debug, Deserialize)]
 struct Response {
-    data: GifResponse,
+    /// A gif, or an empty list when none match the search.
+    data: serde_json::Value,
 }
 
 #[derive(Debug, Deserialize)]
 struct Images {
-    original: OriginalImage,
+    original: Rendition,
+
+    #[serde(default)]
+    downsized: Option<Rendition>,
+
+    #[serde(default)]
+    fixed_width: Option<Rendition>,
 }
 
-#[derive(Debug, Deserialize)]
-struct OriginalImage {
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+struct Rendition {
+    #[serde(default)]
     webp: String,
+
+    /// Giphy sends sizes as strings.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    webp_size: Option<u64>,
+
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    width: Option<u32>,
+
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    height: Option<u32>,
 }
 
 impl<'a> Giphy<'a> {
-    pub fn new(giphy_token: &'a str) -> Giphy<'a> {
+    pub fn new(client: Client, giphy_token: &'a str) -> Giphy<'a> {
         Giphy {
-            client: Client::builder().https_only(true).build().unwrap(),
+            client,
             token: giphy_token,
-            random_url: url!("https://api.giphy.com/v1/gifs/random"),
+            base_url: url!("https://api.giphy.com/v1/"),
+            kind: GiphyKind::Gif,
+            rating: "pg",
+            attribution: ATTRIBUTION,
+            limits: Limits::default(),
+            cache_size: 0,
+            cache_refresh: 1.0,
+            cache: Mutex::new(HashMap::new()),
+            requests: Default::default(),
         }
     }
 
-    pub async fn random(&self, search: &str) -> Result<Gif> {
-        Ok(self
+    /// Share requests in flight and rate limits with every other [`Giphy`] given `requests`.
+    pub fn with_requests(mut self, requests: Arc<Requests>) -> Giphy<'a> {
+        self.requests = requests;
+        self
+    }
+
+    pub fn with_kind(mut self, kind: GiphyKind) -> Giphy<'a> {
+        self.kind = kind;
+        self
+    }
+
+    /// Send requests to `base_url` instead of Giphy's API, such as a proxy.
+    pub fn with_base_url(mut self, mut base_url: Url) -> Giphy<'a> {
+        // Without a trailing slash, joining would replace the last segment
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        self.base_url = base_url;
+        self
+    }
+
+    fn random_url(&self) -> Url {
+        let path = match self.kind {
+            GiphyKind::Gif => "gifs/random",
+            GiphyKind::Sticker => "stickers/random",
+        };
+        self.base_url
+            .join(path)
+            .expect("Giphy path should always join")
+    }
+
+    pub fn with_rating(mut self, rating: &'a str) -> Giphy<'a> {
+        self.rating = rating;
+        self
+    }
+
+    /// Show `attribution` under gifs instead of "Powered By GIPHY".
+    pub fn with_attribution(mut self, attribution: &'a str) -> Giphy<'a> {
+        self.attribution = attribution;
+        self
+    }
+
+    /// Prefer the largest rendition no bigger than `max_bytes`, instead of always the original.
+    pub fn with_max_bytes(mut self, max_bytes: Option<u64>) -> Giphy<'a> {
+        self.limits.max_bytes = max_bytes;
+        self
+    }
+
+    /// Prefer the largest rendition within `max_width` and `max_height` pixels, instead of always
+    /// the original.
+    pub fn with_max_dimensions(
+        mut self,
+        max_width: Option<u32>,
+        max_height: Option<u32>,
+    ) -> Giphy<'a> {
+        self.limits.max_width = max_width;
+        self.limits.max_height = max_height;
+        self
+    }
+
+    /// Cache up to `size` gifs per search, only fetching a new gif with a chance of `refresh`.
+    pub fn with_cache(mut self, size: usize, refresh: f64) -> Giphy<'a> {
+        self.cache_size = size;
+        self.cache_refresh = refresh;
+        self
+    }
+
+    /// Fetch a random gif for `search`, asking again if it has no image.
+    async fn fetch(&self, search: &str) -> Result<Gif, GiphyError> {
+        for attempt in 1..=FETCH_ATTEMPTS {
+            let gif = self.fetch_once(search).await?;
+            if Url::parse(&gif.url).is_ok() {
+                return Ok(gif);
+            }
+            debug!(?gif, search, attempt, "gif has no image, asking again");
+        }
+
+        Err(GiphyError::NoResults(search.to_string()))
+    }
+
+    async fn fetch_once(&self, search: &str) -> Result<Gif, GiphyError> {
+        #[cfg(feature = "metrics")]
+        metrics::counter!("beerbot_giphy_requests_total").increment(1);
+
+        let mut request = self
             .client
-            .get(self.random_url.clone())
-            .query(&[("api_key", self.token), ("tag", search), ("rating", "pg")])
+            .get(self.random_url())
+            .query(&[("api_key", self.token), ("tag", search)]);
+        // Without a rating, Giphy doesn't filter gifs
+        if self.rating != NO_RATING {
+            request = request.query(&[("rating", self.rating)]);
+        }
+        let response = request
             .send()
-            .await?
-            .json::<Response>()
-            .await?
-            .data
-            .into())
+            .await
+            .map_err(|e| GiphyError::Http(e.to_string()))?;
+        self.note_rate_limit(&response);
+        let data = parse::<Response>(response).await?.data;
+        if data.as_array().is_some_and(Vec::is_empty) {
+            return Err(GiphyError::NoResults(search.to_string()));
+        }
+        serde_json::from_value::<GifResponse>(data)
+            .map(|gif| gif.into_gif(self.limits))
+            .map_err(|e| GiphyError::Deserialize(e.to_string()))
+    }
+
+    /// Stop asking until the rate limit resets, if `response` used up the last request or was
+    /// rejected for being over the limit.
+    fn note_rate_limit(&self, response: &HttpResponse) {
+        let header = |name: &str| -> Option<u64> {
+            response.headers().get(name)?.to_str().ok()?.parse().ok()
+        };
+        let remaining = header("X-RateLimit-Remaining");
+        if response.status() != StatusCode::TOO_MANY_REQUESTS && remaining != Some(0) {
+            return;
+        }
+
+        let wait = header("Retry-After")
+            .map(Duration::from_secs)
+            .unwrap_or(RATE_LIMIT_COOLDOWN);
+        warn!(
+            ?wait,
+            ?remaining,
+            "Giphy rate limit reached, not asking until it resets"
+        );
+        self.requests
+            .cooldowns
+            .lock()
+            .expect("Giphy cooldowns poisoned")
+            .insert(self.base_url.clone(), Instant::now() + wait);
+    }
+
+    /// Whether the rate limit was reached and hasn't reset yet.
+    fn cooling_down(&self) -> bool {
+        let mut cooldowns = self
+            .requests
+            .cooldowns
+            .lock()
+            .expect("Giphy cooldowns poisoned");
+        match cooldowns.get(&self.base_url) {
+            Some(until) if Instant::now() < *until => true,
+            Some(_) => {
+                cooldowns.remove(&self.base_url);
+                info!("Giphy rate limit reset, asking again");
+                false
+            }
+            None => false,
+        }
     }
+
+    fn cached(&self, search: &str) -> Option<Gif> {
+        if self.cache_size == 0 {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(self.cache_refresh) {
+            return None;
+        }
+
+        self.cache
+            .lock()
+            .expect("Giphy cache poisoned")
+            .get(search)
+            .and_then(|gifs| gifs.iter().choose(&mut rng))
+            .cloned()
+    }
+
+    fn cache(&self, search: &str, gif: &Gif) {
+        if self.cache_size == 0 {
+            return;
+        }
+
+        let mut cache = self.cache.lock().expect("Giphy cache poisoned");
+        let gifs = cache.entry(search.to_string()).or_default();
+        gifs.push_back(gif.clone());
+        while gifs.len() > self.cache_size {
+            gifs.pop_front();
+        }
+    }
+}
+
+#[async_trait]
+impl GifProvider for Giphy<'_> {
+    async fn random(&self, search: &str) -> Result<Gif, GiphyError> {
+        if let Some(gif) = self.cached(search) {
+            trace!(search, "using cached gif");
+            return Ok(gif);
+        }
+        if self.cooling_down() {
+            trace!(search, "rate limited, not asking");
+            return Err(GiphyError::RateLimited);
+        }
+
+        let key = (
+            self.random_url(),
+            self.rating.to_string(),
+            search.to_string(),
+        );
+        let in_flight = self
+            .requests
+            .in_flight
+            .lock()
+            .expect("Giphy requests poisoned")
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let gif = in_flight
+            .get_or_init(|| async {
+                let gif = self.fetch(search).await;
+                self.requests
+                    .in_flight
+                    .lock()
+                    .expect("Giphy requests poisoned")
+                    .remove(&key);
+                gif
+            })
+            .await
+            .clone()?;
+        self.cache(search, &gif);
+
+        Ok(gif)
+    }
+
+    fn attribution(&self) -> &str {
+        self.attribution
+    }
+}
+
+/// Send `request`, mapping the status and body into a [`GiphyError`] if it failed.
+pub async fn send<T: serde::de::DeserializeOwned>(
+    request: RequestBuilder,
+) -> Result<T, GiphyError> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| GiphyError::Http(e.to_string()))?;
+    parse(response).await
+}
+
+/// Map the status and body of `response` into a [`GiphyError`] if it failed.
+async fn parse<T: serde::de::DeserializeOwned>(response: HttpResponse) -> Result<T, GiphyError> {
+    match response.status() {
+        StatusCode::TOO_MANY_REQUESTS => return Err(GiphyError::RateLimited),
+        status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+            return Err(GiphyError::Unauthorized(status))
+        }
+        status if !status.is_success() => return Err(GiphyError::Http(status.to_string())),
+        _ => {}
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| GiphyError::Deserialize(e.to_string()))
 }
 
-impl From<GifResponse> for Gif {
-    fn from(value: GifResponse) -> Self {
+impl GifResponse {
+    fn into_gif(self, limits: Limits) -> Gif {
+        let rendition = self.images.pick(limits);
         Gif {
-            url: value.images.original.webp,
-            alt_text: value.alt_text,
+            url: rendition.webp,
+            alt_text: self.alt_text,
+            page_url: self.url,
+            width: rendition.width,
+            height: rendition.height,
+        }
+    }
+}
+
+impl Images {
+    /// The largest rendition within `limits`, or the original if none are.
+    fn pick(self, limits: Limits) -> Rendition {
+        if limits.max_bytes.is_none() && limits.max_width.is_none() && limits.max_height.is_none() {
+            return self.original;
+        }
+
+        // A rendition without the size or dimension being limited can't be known to fit
+        let within = |value: Option<u64>, max: Option<u64>| match max {
+            Some(max) => value.is_some_and(|value| value <= max),
+            None => true,
+        };
+        [
+            Some(&self.original),
+            self.downsized.as_ref(),
+            self.fixed_width.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|rendition| !rendition.webp.is_empty())
+        .filter(|rendition| {
+            within(rendition.webp_size, limits.max_bytes)
+                && within(
+                    rendition.width.map(u64::from),
+                    limits.max_width.map(u64::from),
+                )
+                && within(
+                    rendition.height.map(u64::from),
+                    limits.max_height.map(u64::from),
+                )
+        })
+        .max_by_key(|rendition| {
+            (
+                rendition.webp_size,
+                rendition.width.unwrap_or(0) * rendition.height.unwrap_or(0),
+            )
+        })
+        .cloned()
+        .unwrap_or_else(|| self.original.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn rendition(name: &str, size: Option<u64>, dims: Option<(u32, u32)>) -> Rendition {
+        Rendition {
+            webp: format!("https://media.giphy.com/{}.webp", name),
+            webp_size: size,
+            width: dims.map(|(width, _)| width),
+            height: dims.map(|(_, height)| height),
+        }
+    }
+
+    fn images() -> Images {
+        Images {
+            original: rendition("original", Some(5_000_000), Some((480, 480))),
+            downsized: Some(rendition("downsized", Some(1_500_000), Some((480, 480)))),
+            fixed_width: Some(rendition("fixed_width", Some(300_000), Some((200, 200)))),
         }
     }
+
+    fn limits(max_bytes: Option<u64>, max_width: Option<u32>, max_height: Option<u32>) -> Limits {
+        Limits {
+            max_bytes,
+            max_width,
+            max_height,
+        }
+    }
+
+    #[test]
+    fn pick_without_limits_is_the_original() {
+        assert!(images().pick(Limits::default()).webp.contains("original"));
+    }
+
+    #[test]
+    fn pick_largest_within_max_bytes() {
+        let picked = images().pick(limits(Some(2_000_000), None, None));
+
+        assert!(picked.webp.contains("downsized"), "{:?}", picked);
+    }
+
+    #[test]
+    fn pick_largest_within_max_dimensions() {
+        let picked = images().pick(limits(None, Some(300), None));
+        assert!(picked.webp.contains("fixed_width"), "{:?}", picked);
+
+        let picked = images().pick(limits(None, None, Some(300)));
+        assert!(picked.webp.contains("fixed_width"), "{:?}", picked);
+    }
+
+    #[test]
+    fn pick_skips_renditions_missing_the_limited_field() {
+        let mut images = images();
+        images.downsized = Some(rendition("downsized", None, None));
+
+        let picked = images.pick(limits(Some(2_000_000), Some(480), None));
+
+        assert!(picked.webp.contains("fixed_width"), "{:?}", picked);
+    }
+
+    #[test]
+    fn pick_falls_back_to_the_original() {
+        let picked = images().pick(limits(Some(1_000), Some(100), Some(100)));
+
+        assert!(picked.webp.contains("original"), "{:?}", picked);
+    }
+
+    /// A Giphy asking `server`, which only answers `/gifs/random` with `data`, `calls` times.
+    async fn giphy(server: &MockServer, data: serde_json::Value, calls: u64) -> Giphy<'static> {
+        Mock::given(method("GET"))
+            .and(path("/gifs/random"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "data": data })),
+            )
+            .expect(calls)
+            .mount(server)
+            .await;
+        Giphy::new(Client::new(), "token").with_base_url(Url::parse(&server.uri()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn gif_without_an_image_is_asked_for_again_then_no_results() {
+        let server = MockServer::start().await;
+        let data = serde_json::json!({
+            "alt_text": "",
+            "images": { "original": { "webp": "" } },
+        });
+        let giphy = giphy(&server, data, FETCH_ATTEMPTS as u64).await;
+
+        let res = giphy.random("beer").await;
+
+        assert!(matches!(res, Err(GiphyError::NoResults(search)) if search == "beer"));
+    }
+
+    #[tokio::test]
+    async fn no_gifs_for_a_search_is_no_results() {
+        let server = MockServer::start().await;
+        let giphy = giphy(&server, serde_json::json!([]), 1).await;
+
+        let res = giphy.random("beer").await;
+
+        assert!(matches!(res, Err(GiphyError::NoResults(search)) if search == "beer"));
+    }
 }
@@ -0,0 +1,100 @@
+#![cfg_attr(not(feature = "healthcheck"), allow(unused_imports))]
+use crate::commands::Sockets;
+use crate::config::Config;
+use crate::Globals;
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use async_scoped::spawner::use_tokio::Tokio;
+use async_scoped::{Scope, TokioScope};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{debug, info, instrument, warn};
+
+/// Serve health checks on `health_addr`, which is only read once as it can't change without a
+/// restart.
+#[cfg(feature = "healthcheck")]
+pub fn init<'a>(globals: &Arc<Globals>) -> [(Scope<'a, (), Tokio>, ()); 1] {
+    let cfg = globals.cfg.clone();
+    let sockets = globals.sockets.clone();
+    [unsafe {
+        TokioScope::scope(move |s: &mut Scope<'_, (), Tokio>| {
+            s.spawn_cancellable(
+                async move {
+                    let addr = cfg.load().health_addr;
+                    if let Err(e) = serve(addr, cfg, sockets).await {
+                        warn!(?e, "health check server stopped");
+                    }
+                },
+                || (),
+            )
+        })
+    }]
+}
+
+#[cfg(not(feature = "healthcheck"))]
+#[inline]
+pub fn init<'a>(_: &Arc<Globals>) -> [(Scope<'a, (), Tokio>, ()); 0] {
+    []
+}
+
+#[cfg(feature = "healthcheck")]
+#[instrument(skip(cfg, sockets))]
+async fn serve(addr: SocketAddr, cfg: Arc<ArcSwap<Config>>, sockets: Arc<Sockets>) -> Result<()> {
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind health check to {}", addr))?;
+    info!("serving health checks");
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        // Crons can be added or disabled by reloading, so they're counted for each request
+        let crons = cfg.load().crons.iter().filter(|c| c.enabled).count();
+        let connected = sockets.connected();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, crons, connected).await {
+                debug!(?e, %peer, "failed to respond to health check");
+            }
+        });
+    }
+}
+
+/// Respond to a single request. `/healthz` is always ok once serving, whereas `/readyz` is only
+/// ok while every workspace's socket mode listener is `connected`.
+#[cfg(feature = "healthcheck")]
+async fn respond(mut stream: tokio::net::TcpStream, crons: usize, connected: bool) -> Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut buf = [0; 1024];
+    let len = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or_default();
+
+    let (status, ok) = match path {
+        "/healthz" => ("200 OK", true),
+        "/readyz" if connected => ("200 OK", true),
+        "/readyz" => ("503 Service Unavailable", false),
+        _ => ("404 Not Found", false),
+    };
+    debug!(path, status, "health check");
+
+    let body = serde_json::json!({ "ok": ok, "crons": crons }).to_string();
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,292 @@
+use crate::config::Workspace;
+use crate::Globals;
+use slack_morphism::blocks::{
+    SlackActionBlockElement, SlackActionsBlock, SlackBlock, SlackBlockButtonElement, SlackBlockId,
+    SlackBlockPlainTextOnly,
+};
+use slack_morphism::events::{SlackInteractionActionContainer, SlackInteractionEvent};
+use slack_morphism::listener::SlackClientEventsUserState;
+use slack_morphism::prelude::{SlackApiChatUpdateRequest, SlackHyperClient};
+use slack_morphism::{SlackActionId, SlackChannelId, SlackTs, SlackUserId, UserCallbackResult};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, instrument};
+
+/// Block the "I'm in" button is in, so it can be found again to update the count.
+const IM_IN_BLOCK: &str = "beer-bot-im-in";
+
+/// Action sent when the "I'm in" button is clicked.
+const IM_IN_ACTION: &str = "beer-bot-im-in";
+
+/// Longest value Slack accepts for a button.
+const MAX_VALUE_LEN: usize = 2000;
+
+/// Room left in the button's value for how many didn't fit, e.g. `,+1234`.
+const UNLISTED_LEN: usize = 16;
+
+/// How long a tally is kept after it was last clicked, after which it's counted from the button
+/// again.
+const FORGET_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Who's in for an announcement.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tally {
+    users: Vec<SlackUserId>,
+
+    /// How many more are in than are listed in `users`, as they were in before a restart, or
+    /// before the tally was forgotten, but didn't fit in the button's value. Who they are isn't
+    /// known, so any of them clicking again are counted twice.
+    unlisted: usize,
+}
+
+impl Tally {
+    fn count(&self) -> usize {
+        self.users.len() + self.unlisted
+    }
+}
+
+/// A tally, locked while it's updated, which is `None` until it's first clicked.
+type LockedTally = Arc<tokio::sync::Mutex<Option<Tally>>>;
+
+/// Who's in for each announcement clicked in the last [`FORGET_AFTER`], by channel and message,
+/// with when it was last clicked. Each is locked while it's updated, so clicks at the same time
+/// are counted one after the other rather than each overwriting the message with only its own
+/// click.
+#[derive(Debug, Default)]
+pub struct Tallies {
+    tallies: Mutex<HashMap<(SlackChannelId, SlackTs), (Instant, LockedTally)>>,
+}
+
+impl Tallies {
+    /// The tally for the message `ts` in `channel`, clicked `now`, forgetting any not clicked
+    /// since [`FORGET_AFTER`] ago.
+    fn get(&self, channel: &SlackChannelId, ts: &SlackTs, now: Instant) -> LockedTally {
+        let mut tallies = self.tallies.lock().expect("Tallies poisoned");
+        tallies.retain(|_, (clicked, _)| now.duration_since(*clicked) < FORGET_AFTER);
+        let (clicked, tally) = tallies
+            .entry((channel.clone(), ts.clone()))
+            .or_insert_with(|| (now, Default::default()));
+        *clicked = now;
+        tally.clone()
+    }
+}
+
+/// Block with an "I'm in" button, showing how many are in. As many of the users as fit are kept in
+/// the button's value, followed by how many more didn't, e.g. `U012,U345,+2`, so the tally
+/// survives restarts without storing it anywhere.
+pub fn im_in_block(tally: &Tally) -> SlackBlock {
+    let text = if tally.count() == 0 {
+        "I'm in 🍺".to_string()
+    } else {
+        format!("I'm in 🍺 ({})", tally.count())
+    };
+    let mut button = SlackBlockButtonElement::new(SlackBlockPlainTextOnly::from(text))
+        .with_action_id(SlackActionId(IM_IN_ACTION.to_string()));
+    let mut values = Vec::new();
+    let mut len = UNLISTED_LEN;
+    for user in &tally.users {
+        len += user.0.len() + 1;
+        if len > MAX_VALUE_LEN {
+            break;
+        }
+        values.push(user.0.clone());
+    }
+    let unlisted = tally.count() - values.len();
+    if unlisted > 0 {
+        values.push(format!("+{}", unlisted));
+    }
+    let value = values.join(",");
+    if !value.is_empty() {
+        button = button.with_value(value);
+    }
+
+    SlackBlock::Actions(
+        SlackActionsBlock::new(vec![SlackActionBlockElement::Button(button)])
+            .with_block_id(SlackBlockId(IM_IN_BLOCK.to_string())),
+    )
+}
+
+/// Who's in according to the "I'm in" button in `block`, if it's the button's block.
+fn im_in_tally(block: &SlackBlock) -> Option<Tally> {
+    let SlackBlock::Actions(actions) = block else {
+        return None;
+    };
+    if actions.block_id.as_ref()?.0 != IM_IN_BLOCK {
+        return None;
+    }
+
+    let mut tally = Tally::default();
+    let value = actions
+        .elements
+        .iter()
+        .find_map(|element| match element {
+            SlackActionBlockElement::Button(button) => button.value.as_deref(),
+            _ => None,
+        })
+        .unwrap_or_default();
+    for user in value.split(',').filter(|user| !user.is_empty()) {
+        match user.strip_prefix('+') {
+            Some(unlisted) => tally.unlisted += unlisted.parse::<usize>().unwrap_or_default(),
+            None => tally.users.push(SlackUserId(user.to_string())),
+        }
+    }
+    Some(tally)
+}
+
+/// Count whoever clicked "I'm in" on an announcement, updating the announcement with the count.
+/// The message in the click may already be out of date, so it's only counted from for the first
+/// click since starting.
+#[instrument(skip_all)]
+pub async fn handle_interactions(
+    event: SlackInteractionEvent,
+    client: Arc<SlackHyperClient>,
+    states: SlackClientEventsUserState,
+) -> UserCallbackResult<()> {
+    let SlackInteractionEvent::BlockActions(event) = event else {
+        debug!("ignoring interaction");
+        return Ok(());
+    };
+    if !event
+        .actions
+        .iter()
+        .flatten()
+        .any(|action| action.action_id.0 == IM_IN_ACTION)
+    {
+        return Ok(());
+    }
+    let (Some(user), Some(message), SlackInteractionActionContainer::Message(container)) =
+        (event.user, event.message, event.container)
+    else {
+        debug!("ignoring click without a message");
+        return Ok(());
+    };
+    // Ephemeral messages, like previews, can't be updated
+    if container.is_ephemeral == Some(true) {
+        return Ok(());
+    }
+    let Some(channel) = container
+        .channel_id
+        .or_else(|| event.channel.map(|channel| channel.id))
+    else {
+        debug!("ignoring click without a channel");
+        return Ok(());
+    };
+
+    let mut content = message.content;
+    let Some((block, clicked)) = content.blocks.iter_mut().flatten().find_map(|block| {
+        let tally = im_in_tally(block)?;
+        Some((block, tally))
+    }) else {
+        debug!("ignoring click on a message without the button");
+        return Ok(());
+    };
+
+    let (token, globals) = {
+        let states = states.read().await;
+        (
+            states
+                .get_user_state::<Workspace>()
+                .expect("Unable to get workspace")
+                .token
+                .clone(),
+            states
+                .get_user_state::<Arc<Globals>>()
+                .expect("Unable to get globals")
+                .clone(),
+        )
+    };
+    let tally = globals
+        .tallies
+        .get(&channel, &container.message_ts, Instant::now());
+    let mut tally = tally.lock().await;
+    let mut updated = tally.clone().unwrap_or(clicked);
+    if updated.users.contains(&user.id) {
+        debug!(user = %user.id, "already in");
+        return Ok(());
+    }
+    updated.users.push(user.id);
+    *block = im_in_block(&updated);
+    info!(count = updated.count(), "someone's in");
+
+    client
+        .open_session(&token)
+        .chat_update(&SlackApiChatUpdateRequest::new(
+            channel,
+            content,
+            container.message_ts,
+        ))
+        .await?;
+    // Only once it's shown, so a failed update doesn't count them
+    *tally = Some(updated);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users(count: usize) -> Vec<SlackUserId> {
+        (0..count)
+            .map(|i| SlackUserId(format!("U{:010}", i)))
+            .collect()
+    }
+
+    #[test]
+    fn no_one_is_in_to_start_with() {
+        let tally = im_in_tally(&im_in_block(&Tally::default())).unwrap();
+        assert_eq!(tally, Tally::default());
+    }
+
+    #[test]
+    fn tallies_survive_the_button() {
+        let tally = Tally {
+            users: users(3),
+            unlisted: 0,
+        };
+        assert_eq!(im_in_tally(&im_in_block(&tally)).unwrap(), tally);
+    }
+
+    #[test]
+    fn users_who_dont_fit_are_still_counted() {
+        let tally = Tally {
+            users: users(500),
+            unlisted: 0,
+        };
+        let block = im_in_block(&tally);
+        let SlackBlock::Actions(actions) = &block else {
+            panic!("not an actions block");
+        };
+        let SlackActionBlockElement::Button(button) = &actions.elements[0] else {
+            panic!("not a button");
+        };
+        assert!(button.value.as_ref().unwrap().len() <= MAX_VALUE_LEN);
+
+        let restored = im_in_tally(&block).unwrap();
+        assert_eq!(restored.count(), 500);
+        assert_eq!(restored.users, tally.users[..restored.users.len()]);
+    }
+
+    #[test]
+    fn tallies_not_clicked_for_a_while_are_forgotten() {
+        let tallies = Tallies::default();
+        let channel = SlackChannelId("C1".to_string());
+        let start = Instant::now();
+        tallies.get(&channel, &SlackTs("1.0".to_string()), start);
+        tallies.get(&channel, &SlackTs("2.0".to_string()), start);
+
+        let later = start + FORGET_AFTER;
+        tallies.get(
+            &channel,
+            &SlackTs("2.0".to_string()),
+            later - Duration::from_secs(1),
+        );
+        tallies.get(&channel, &SlackTs("3.0".to_string()), later);
+
+        let tallies = tallies.tallies.lock().unwrap();
+        assert!(!tallies.contains_key(&(channel.clone(), SlackTs("1.0".to_string()))));
+        assert!(tallies.contains_key(&(channel.clone(), SlackTs("2.0".to_string()))));
+        assert_eq!(tallies.len(), 2);
+    }
+}
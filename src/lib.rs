@@ -0,0 +1,1256 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use arc_swap::ArcSwap;
+use async_scoped::spawner::use_tokio::Tokio;
+use async_scoped::{Scope, TokioScope};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use chrono_humanize::HumanTime;
+use rand::Rng;
+use slack_morphism::errors::{SlackClientError, SlackRateLimitError};
+use slack_morphism::prelude::*;
+use tokio::sync::{watch, Mutex, OnceCell};
+use tracing::{debug, error, info, instrument, trace, warn, Level};
+
+use crate::additions::Additions;
+use crate::commands::Sockets;
+pub use crate::config::{Config, CronJob};
+use crate::config::{Notify, Source};
+use crate::limiter::{Limiter, WorkspaceChannel};
+pub use crate::message::MessageBuilder;
+use crate::message::{Announcement, CronPicks, GifRequests, HttpClient};
+use crate::mutes::Mutes;
+use crate::pause::Pause;
+use crate::post::Poster;
+use crate::skips::Skips;
+use crate::stats::Stats;
+
+mod additions;
+mod audit;
+mod commands;
+pub mod config;
+#[cfg(feature = "discord")]
+mod discord;
+mod exec;
+#[cfg(feature = "giphy")]
+mod gif;
+#[cfg(feature = "giphy")]
+mod giphy;
+mod health;
+#[cfg(feature = "interactions")]
+mod interactions;
+mod limiter;
+pub mod message;
+mod mutes;
+mod pause;
+mod persist;
+mod post;
+mod skips;
+mod stats;
+#[cfg(feature = "giphy")]
+mod tenor;
+mod webhook;
+
+/// Text of the parent message each announcement is threaded under in `thread_mode`.
+const DAILY_THREAD_TEXT: &str = "Beer Bot daily 🍺";
+
+/// How far away a cron must be for it to be scheduled with Slack when `use_slack_scheduler` is on.
+const SLACK_SCHEDULER_THRESHOLD: Duration = Duration::from_secs(60 * 60);
+
+/// Most channel members mentioned individually when leaving out muted users.
+const MAX_MEMBERS: usize = 1000;
+
+/// Most members or users fetched from Slack in each page.
+const PAGE_SIZE: u16 = 200;
+
+/// Appended to `@here` once anyone has muted, as Slack can't leave them out of it.
+const HERE_MUTED_NOTE: &str = "(pings everyone active, even if muted)";
+
+/// How long after a message scheduled with Slack is due to wait before scheduling the next.
+const SLACK_SCHEDULER_GRACE: Duration = Duration::from_secs(5);
+
+/// What became of an announcement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Posted,
+
+    /// Not sent, as the channel was posted to less than `min_interval_seconds` ago.
+    TooSoon,
+
+    /// Not sent, as the same message was posted to the channel less than `dedup_window_seconds`
+    /// ago.
+    Duplicate,
+
+    /// Sending failed, which is already logged.
+    Failed,
+
+    /// Not sent, as `dry_run` is on, only logged.
+    #[cfg_attr(not(feature = "commands"), allow(dead_code))]
+    DryRun,
+}
+
+/// State shared by every cron and command.
+struct Globals {
+    client: Arc<SlackHyperClient>,
+
+    /// Current config, swapped out when reloaded.
+    cfg: Arc<ArcSwap<Config>>,
+
+    /// When every `@every` interval is counted from, so reloading doesn't shift them.
+    interval_start: DateTime<Local>,
+
+    /// Today's parent message per channel for `thread_mode`, with the date it was posted for.
+    daily_threads: Mutex<HashMap<WorkspaceChannel, (NaiveDate, SlackTs)>>,
+
+    /// Whether each cron's `thread_ts` was found in each channel, so it's only looked up once.
+    topic_threads: Mutex<HashMap<(WorkspaceChannel, SlackTs), bool>>,
+
+    /// Client shared by every [`MessageBuilder`] to fetch gifs.
+    http: HttpClient,
+
+    /// Requests for gifs shared by every [`MessageBuilder`].
+    gif_requests: GifRequests,
+
+    /// Announcements skipped with `/skip-next`.
+    skips: Arc<Skips>,
+
+    /// Whether every cron is paused with `/beer-pause`.
+    pause: Arc<Pause>,
+
+    /// When each channel was last posted to, for `min_interval_seconds`.
+    limiter: Arc<Limiter>,
+
+    stats: Arc<Stats>,
+
+    /// Messages added with `/beer-add`.
+    additions: Arc<Additions>,
+
+    /// Users who muted `notify` with `/beer-mute`.
+    mutes: Arc<Mutes>,
+
+    /// Messages each cron picked in each workspace, so re-rolls and `sequential` carry on across
+    /// reloads.
+    picks: CronPicks,
+
+    /// Whether each workspace's socket for commands is connected, for `/readyz`.
+    sockets: Arc<Sockets>,
+
+    /// Who's in for each announcement, from clicking "I'm in".
+    #[cfg(feature = "interactions")]
+    tallies: interactions::Tallies,
+}
+
+/// Receives SIGHUP, used to reload the config.
+#[cfg(unix)]
+struct Hangup(tokio::signal::unix::Signal);
+
+/// SIGHUP doesn't exist, so never receives anything.
+#[cfg(not(unix))]
+struct Hangup;
+
+#[cfg(unix)]
+impl Hangup {
+    fn new() -> Result<Hangup> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Hangup(
+            signal(SignalKind::hangup()).with_context(|| "Failed to listen for SIGHUP")?,
+        ))
+    }
+
+    async fn recv(&mut self) {
+        self.0.recv().await;
+    }
+}
+
+#[cfg(not(unix))]
+impl Hangup {
+    fn new() -> Result<Hangup> {
+        Ok(Hangup)
+    }
+
+    async fn recv(&mut self) {
+        std::future::pending().await
+    }
+}
+
+/// Announce for `cfg`, and answer commands if enabled, until ctrl+c, reloading the config on
+/// SIGHUP. `cfg` should already be validated, and logging set up by the caller.
+pub async fn run(cfg: Config) -> Result<()> {
+    // Fails if the caller already installed one, which is just as good
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+    let connector = SlackClientHyperHttpsConnector::new()
+        .with_context(|| "Failed to initialise HTTPs client")?;
+    run_with(cfg, connector, message::http_client()?).await
+}
+
+/// [`run`], reaching Slack through `connector` and everything else, such as Giphy, through `http`,
+/// e.g. ones pointed at a mock server in tests.
+#[instrument(skip_all)]
+pub async fn run_with(
+    cfg: Config,
+    connector: SlackClientHyperHttpsConnector,
+    http: HttpClient,
+) -> Result<()> {
+    let mut cfg = cfg;
+    let interval_start = Local::now();
+    cfg.count_intervals_from(interval_start);
+    let cfg = Arc::new(cfg);
+    debug!(config = %cfg);
+
+    let client = Arc::new(SlackClient::new(connector));
+    check_tokens(&client, &cfg).await?;
+    let globals = Arc::new(Globals {
+        client: client.clone(),
+        cfg: Arc::new(ArcSwap::new(cfg.clone())),
+        interval_start,
+        daily_threads: Default::default(),
+        topic_threads: Default::default(),
+        http,
+        gif_requests: Default::default(),
+        skips: Default::default(),
+        pause: Arc::new(Pause::load().await),
+        limiter: Default::default(),
+        stats: Arc::new(Stats::load(cfg.stats_path.clone()).await),
+        additions: Arc::new(Additions::load().await),
+        mutes: Arc::new(Mutes::load().await),
+        picks: Default::default(),
+        sockets: Default::default(),
+        #[cfg(feature = "interactions")]
+        tallies: Default::default(),
+    });
+
+    let _command_tasks = commands::init(&globals);
+    let _health_tasks = health::init(&globals);
+    let _stats_tasks = stats::init(globals.stats.clone());
+    let mut hangup = Hangup::new()?;
+
+    if cfg.announce_on_start {
+        announce_start(&cfg, &globals).await;
+    }
+
+    info!("Beer Bot is ready");
+
+    loop {
+        let cfg = globals.cfg.load_full();
+        let workspaces = cfg.all_workspaces();
+        let (shutdown_tx, shutdown_rx) = watch::channel(());
+        for (idx, _) in cfg.crons.iter().enumerate().filter(|(_, job)| !job.enabled) {
+            info!("cron {} disabled", idx);
+        }
+
+        let cron_tasks = workspaces
+            .iter()
+            .enumerate()
+            .flat_map(|(ws_idx, workspace)| {
+                cfg.crons
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, job)| job.enabled)
+                    .map(move |(idx, job)| (ws_idx, workspace, idx, job))
+            })
+            .map(|(ws_idx, workspace, idx, job)| unsafe {
+                // Per cron channels are only for the top-level workspace
+                let channel_ids = if ws_idx == 0 {
+                    job.channel_ids(&cfg)
+                } else {
+                    vec![&workspace.channel_id]
+                };
+                let schedule = spawn_schedule(
+                    idx,
+                    job,
+                    &workspace.token,
+                    channel_ids,
+                    &globals,
+                    MessageBuilder::new(&cfg, &globals.http)
+                        .with_gif_requests(&globals.gif_requests)
+                        .with_notify(job.notify)
+                        .with_timezone(job.timezone)
+                        .with_user_group(job.user_group.clone())
+                        .with_additions(&globals.additions)
+                        .with_picks(globals.picks.get(idx, &workspace.token))
+                        .with_footer(cfg.debug_footer.then(|| {
+                            job.name
+                                .clone()
+                                .unwrap_or_else(|| format!("crons[{}]", idx))
+                        })),
+                    shutdown_rx.clone(),
+                );
+                TokioScope::scope(move |s: &mut Scope<'_, (), Tokio>| {
+                    s.spawn_cancellable(
+                        async move {
+                            if let Err(e) = schedule.await {
+                                warn!(?e)
+                            }
+                        },
+                        || (),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let reload = loop {
+            tokio::select! {
+                res = tokio::signal::ctrl_c() => {
+                    res.with_context(|| "Failed to wait for ctrl+c")?;
+                    break false;
+                }
+                _ = hangup.recv() => match reload_config(&cfg, globals.interval_start).await {
+                    Ok(new) => {
+                        globals.cfg.store(Arc::new(new));
+                        break true;
+                    }
+                    Err(e) => warn!(?e, "failed to reload config, keeping current config"),
+                }
+            }
+        };
+
+        let timeout = Duration::from_millis(cfg.shutdown_timeout_ms);
+        if reload {
+            info!(crons = cron_tasks.len(), ?timeout, "Beer Bot is reloading");
+        } else {
+            info!(crons = cron_tasks.len(), ?timeout, "Beer Bot is stopping");
+        }
+
+        // Let crons finish any announcement they are part way through sending, up to the timeout
+        shutdown_tx.send_replace(());
+        let mut cron_tasks = cron_tasks;
+        let drained = tokio::time::timeout(timeout, async {
+            for (scope, _) in cron_tasks.iter_mut() {
+                scope.collect().await;
+            }
+        })
+        .await;
+        if drained.is_err() {
+            let active = cron_tasks
+                .iter()
+                .filter(|(scope, _)| scope.remaining() > 0)
+                .count();
+            warn!(
+                active,
+                ?timeout,
+                "crons still sending at shutdown_timeout_ms, cancelling them"
+            );
+            for (scope, _) in cron_tasks.iter_mut() {
+                scope.cancel();
+            }
+        }
+        debug!("crons stopped");
+
+        if !reload {
+            break;
+        }
+    }
+
+    if let Err(e) = globals.stats.flush().await {
+        warn!(?e, "failed to write stats");
+    }
+
+    Ok(())
+}
+
+/// Check every workspace's tokens with Slack, so a bad token is found now rather than when a cron
+/// fires.
+#[instrument(skip_all)]
+async fn check_tokens(client: &SlackHyperClient, cfg: &Config) -> Result<()> {
+    for workspace in cfg.all_workspaces() {
+        if post::uses_token(cfg, &workspace.token) {
+            let resp = client
+                .open_session(&workspace.token)
+                .auth_test()
+                .await
+                .with_context(|| {
+                    format!("Slack rejected the token for {}", workspace.channel_id)
+                })?;
+            info!(user = ?resp.user, team = ?resp.team, "authenticated");
+        }
+
+        // auth.test doesn't accept app level tokens
+        #[cfg(feature = "commands")]
+        if let Some(socket_token) = &workspace.socket_token {
+            client
+                .open_session(socket_token)
+                .apps_connections_open(&SlackApiAppsConnectionOpenRequest::new())
+                .await
+                .with_context(|| {
+                    format!(
+                        "Slack rejected the socket token for {}",
+                        workspace.channel_id
+                    )
+                })?;
+        }
+    }
+
+    Ok(())
+}
+
+#[instrument(skip_all, fields(idx = idx, cron = %job, channels = %channel_ids.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", ")))]
+async fn spawn_schedule(
+    idx: usize,
+    job: &CronJob,
+    token: &SlackApiToken,
+    channel_ids: Vec<&SlackChannelId>,
+    globals: &Globals,
+    builder: MessageBuilder<'_>,
+    mut shutdown: watch::Receiver<()>,
+) -> Result<()> {
+    loop {
+        let now = Local::now();
+        if let Some(next) = job.next_occurrence(now) {
+            for skipped in job.scheduled(now).take_while(|dt| *dt < next) {
+                info!(?skipped, "skipping announcement on a skipped date");
+            }
+
+            // Channels to post to when due, rather than scheduling with Slack
+            let mut due = channel_ids.clone();
+            // Webhooks and Discord can't schedule messages
+            let use_slack_scheduler = {
+                let cfg = globals.cfg.load();
+                cfg.use_slack_scheduler && post::uses_token(&cfg, token)
+            };
+            if use_slack_scheduler && until(next) > SLACK_SCHEDULER_THRESHOLD {
+                due.clear();
+                if globals.pause.is_paused() {
+                    info!(?next, "paused, not scheduling");
+                } else if is_quiet(job, next, globals) {
+                    info!(?next, "in quiet hours, not scheduling");
+                } else if globals.skips.is_skipped(idx, next) {
+                    info!(?next, "skipping announcement");
+                } else {
+                    let content = OnceCell::new();
+                    for channel_id in &channel_ids {
+                        if let Err(e) = schedule_message(
+                            next, job, token, channel_id, globals, &builder, &content,
+                        )
+                        .await
+                        {
+                            warn!(?e, %channel_id, "failed to schedule message, sending when due instead");
+                            due.push(*channel_id);
+                        }
+                    }
+                }
+
+                if due.is_empty() {
+                    // Wait until it's been sent, so the next occurrence is scheduled next
+                    let delta = until(next) + SLACK_SCHEDULER_GRACE;
+                    trace!(duration = ?delta, "sleeping until scheduled message sent");
+                    tokio::select! {
+                        _ = tokio::time::sleep(delta) => continue,
+                        _ = shutdown.changed() => {
+                            info!("cron {} stopped", idx);
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+
+            let delta = until(next);
+            let jitter = jitter(job, next, globals.cfg.load().jitter_seconds);
+            trace!(duration = ?delta, ?jitter, "sleeping");
+            tokio::select! {
+                _ = tokio::time::sleep(delta + jitter) => {}
+                _ = shutdown.changed() => {
+                    info!("cron {} stopped", idx);
+                    return Ok(());
+                }
+            }
+            trace!("awoken");
+            if globals.pause.is_paused() {
+                info!("paused, not sending");
+                continue;
+            }
+            // Jitter may have delayed it into quiet hours, so check when it's actually sent
+            if is_quiet(job, Local::now(), globals) {
+                info!("in quiet hours, not sending");
+                continue;
+            }
+            if globals.skips.is_skipped(idx, next) {
+                info!("skipping announcement");
+                continue;
+            }
+            let config = globals.cfg.load_full();
+
+            let announcement = match builder.build_announcement().await {
+                Ok(announcement) => announcement,
+                Err(e) => {
+                    // Only this announcement is missed, the next may well build
+                    warn!(?e, "failed to build announcement, not sending");
+                    continue;
+                }
+            };
+            if config.dry_run {
+                info!(
+                    content = serde_json::to_string(&announcement.content)?,
+                    "dry run, not sending"
+                );
+                continue;
+            }
+
+            if let Some(poster) = post::poster(&config, &globals.http, token) {
+                announce_with(
+                    Some(job),
+                    token,
+                    poster.as_ref(),
+                    &announcement,
+                    &config,
+                    globals,
+                )
+                .await;
+                continue;
+            }
+
+            for channel_id in &due {
+                announce(
+                    Some(job),
+                    token,
+                    channel_id,
+                    &announcement,
+                    &config,
+                    globals,
+                )
+                .await;
+            }
+        } else {
+            // Only this cron stops, the rest keep announcing
+            error!(
+                "cron {} = '{}' never fires again, disabling it",
+                idx, job.schedule
+            );
+            return Ok(());
+        }
+    }
+}
+
+/// Claim a post of `message` to `channel`, unless the channel was posted to too recently or the
+/// same message was just posted there. Claims are only kept for posts which are sent.
+fn claim(
+    channel: &WorkspaceChannel,
+    message: &str,
+    config: &Config,
+    globals: &Globals,
+) -> Result<(), Outcome> {
+    if !globals.limiter.try_claim_message(
+        channel,
+        message,
+        Duration::from_secs(config.dedup_window_seconds),
+    ) {
+        info!(message, "same message just sent, not sending");
+        return Err(Outcome::Duplicate);
+    }
+    if !globals
+        .limiter
+        .try_claim(channel, Duration::from_secs(config.min_interval_seconds))
+    {
+        globals.limiter.release_message(channel, message);
+        warn!("announced too recently, not sending");
+        return Err(Outcome::TooSoon);
+    }
+    Ok(())
+}
+
+/// Give back a claim on a post which failed, so it can be retried.
+fn release(channel: &WorkspaceChannel, message: &str, globals: &Globals) {
+    globals.limiter.release(channel);
+    globals.limiter.release_message(channel, message);
+}
+
+/// Post `announcement` to `channel_id` with `token`, only logging any failure so other channels
+/// are still posted to. `job` is the cron announcing, if it's not from a command.
+#[instrument(skip_all, fields(%channel_id))]
+async fn announce(
+    job: Option<&CronJob>,
+    token: &SlackApiToken,
+    channel_id: &SlackChannelId,
+    announcement: &Announcement,
+    config: &Config,
+    globals: &Globals,
+) -> Outcome {
+    let channel = WorkspaceChannel::new(token, channel_id);
+    if let Err(outcome) = claim(&channel, &announcement.message, config, globals) {
+        return outcome;
+    }
+
+    let session = &globals.client.open_session(token);
+    let content = without_muted(job, channel_id, session, &announcement.content, globals).await;
+    let mut request = SlackApiChatPostMessageRequest::new(channel_id.clone(), content.into_owned())
+        .opt_username(job.and_then(|job| job.username.clone()))
+        .opt_icon_emoji(job.and_then(|job| job.icon_emoji.clone()))
+        .opt_unfurl_links(config.unfurl())
+        .opt_unfurl_media(config.unfurl());
+    if let Some(ts) = thread(job, &channel, session, config, globals).await {
+        request = request.with_thread_ts(ts);
+    }
+
+    match post_message(session, &request, config.post_retries).await {
+        Ok(resp) => {
+            #[cfg(feature = "metrics")]
+            metrics::counter!("beerbot_messages_posted_total", "channel" => channel_id.to_string())
+                .increment(1);
+            globals.stats.record_post();
+            if let Some(source) = &announcement.source {
+                // With thread_mode the announcement is already a reply, so reply in the same thread
+                let thread_ts = request.thread_ts.clone().unwrap_or(resp.ts.clone());
+                let reply = SlackApiChatPostMessageRequest::new(
+                    resp.channel.clone(),
+                    SlackMessageContent::new().with_text(source.to_string()),
+                )
+                .with_thread_ts(thread_ts);
+                if let Err(e) = post_message(session, &reply, config.post_retries).await {
+                    warn!(?e, "failed to reply with gif source");
+                }
+            }
+            add_reactions(session, &resp, &config.auto_reactions).await;
+            exec::on_post(config, channel_id, &request.content);
+            if let Err(e) =
+                audit::record(config, &globals.http, channel_id, &request.content, job).await
+            {
+                warn!(?e, "failed to audit message");
+            }
+            Outcome::Posted
+        }
+        Err(e) => {
+            #[cfg(feature = "metrics")]
+            metrics::counter!("beerbot_post_failures_total", "channel" => channel_id.to_string())
+                .increment(1);
+            match e {
+                SlackClientError::ApiError(e) => {
+                    warn!(code = e.code, ?e, "Slack rejected the message")
+                }
+                e => warn!(?e, "failed to send message"),
+            }
+            if let Some(ts) = job.and_then(|job| job.thread_ts.as_ref()) {
+                // It may have been deleted since it was found, so look for it again next time
+                globals
+                    .topic_threads
+                    .lock()
+                    .await
+                    .remove(&(channel.clone(), ts.clone()));
+            }
+            release(&channel, &announcement.message, globals);
+            Outcome::Failed
+        }
+    }
+}
+
+/// Post that Beer Bot is online to every workspace's channel, only logging any failure.
+#[instrument(skip_all)]
+async fn announce_start(cfg: &Config, globals: &Globals) {
+    let now = Local::now();
+    let next = cfg
+        .next_drink(now)
+        .map(|dt| HumanTime::from(dt - now).to_string())
+        .unwrap_or_else(|| "in some time".to_string());
+    let content = SlackMessageContent::new()
+        .with_text(format!("Beer Bot is online 🍺 (next round {})", next));
+    if cfg.dry_run {
+        info!(text = content.text, "dry run, not announcing start");
+        return;
+    }
+
+    for workspace in cfg.all_workspaces() {
+        let res = match post::poster(cfg, &globals.http, &workspace.token) {
+            Some(poster) => poster.post(&content).await,
+            None => post_message(
+                &globals.client.open_session(&workspace.token),
+                &SlackApiChatPostMessageRequest::new(workspace.channel_id.clone(), content.clone()),
+                cfg.post_retries,
+            )
+            .await
+            .map(|_| ())
+            .map_err(Into::into),
+        };
+        if let Err(e) = res {
+            warn!(?e, channel = %workspace.channel_id, "failed to announce start");
+        }
+    }
+}
+
+/// `content` with `job`'s `notify` mention leaving out muted users, once anyone has muted. Falls
+/// back to mentioning everyone if that fails.
+async fn without_muted<'c>(
+    job: Option<&CronJob>,
+    channel_id: &SlackChannelId,
+    session: &SlackClientSession<'_, SlackClientHyperHttpsConnector>,
+    content: &'c SlackMessageContent,
+    globals: &Globals,
+) -> Cow<'c, SlackMessageContent> {
+    let Some(mention) = job.and_then(|job| job.notify.mention()) else {
+        return Cow::Borrowed(content);
+    };
+    let muted = globals.mutes.users();
+    if muted.is_empty() {
+        return Cow::Borrowed(content);
+    }
+
+    match mention_unmuted(session, channel_id, mention, &muted, content).await {
+        Ok(unmuted) => Cow::Owned(unmuted),
+        Err(e) => {
+            warn!(?e, "failed to leave out muted users, mentioning everyone");
+            Cow::Borrowed(content)
+        }
+    }
+}
+
+/// `content` with `mention` swapped for mentions of each member of `channel_id` who isn't
+/// `muted`, or a bot, as Slack can't leave anyone out of `@channel`. `@here` is kept with a note
+/// instead, as mentioning each member would ping those who are away too.
+async fn mention_unmuted(
+    session: &SlackClientSession<'_, SlackClientHyperHttpsConnector>,
+    channel_id: &SlackChannelId,
+    mention: &str,
+    muted: &[SlackUserId],
+    content: &SlackMessageContent,
+) -> Result<SlackMessageContent> {
+    if Some(mention) == Notify::Here.mention() {
+        return message::replace_mention(
+            content,
+            mention,
+            &format!("{} {}", mention, HERE_MUTED_NOTE),
+        );
+    }
+
+    let members = channel_members(session, channel_id).await?;
+    let mut ignored = bot_users(session).await?;
+    ignored.insert(session.auth_test().await?.user_id);
+    ignored.extend(muted.iter().cloned());
+    let unmuted = members
+        .iter()
+        .filter(|member| !ignored.contains(member))
+        .take(MAX_MEMBERS)
+        .collect::<Vec<_>>();
+    debug!(
+        members = members.len(),
+        mentioned = unmuted.len(),
+        muted = muted.len(),
+        "mentioning unmuted members"
+    );
+    let mentions = unmuted
+        .iter()
+        .map(|member| format!("<@{}>", member))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    message::replace_mention(content, mention, &mentions)
+}
+
+/// Every member of `channel_id`, a page at a time.
+async fn channel_members(
+    session: &SlackClientSession<'_, SlackClientHyperHttpsConnector>,
+    channel_id: &SlackChannelId,
+) -> Result<Vec<SlackUserId>> {
+    let mut members = Vec::new();
+    let mut cursor = None;
+    loop {
+        let resp = session
+            .conversations_members(
+                &SlackApiConversationsMembersRequest::new()
+                    .with_channel(channel_id.clone())
+                    .with_limit(PAGE_SIZE)
+                    .opt_cursor(cursor),
+            )
+            .await?;
+        members.extend(resp.members);
+        cursor = resp.response_metadata.and_then(|meta| meta.next_cursor);
+        if cursor.is_none() {
+            return Ok(members);
+        }
+    }
+}
+
+/// Every bot or deactivated user in the workspace, a page at a time, as they're listed as channel
+/// members but can't be pinged.
+async fn bot_users(
+    session: &SlackClientSession<'_, SlackClientHyperHttpsConnector>,
+) -> Result<HashSet<SlackUserId>> {
+    let mut bots = HashSet::new();
+    let mut cursor = None;
+    loop {
+        let resp = session
+            .users_list(
+                &SlackApiUsersListRequest::new()
+                    .with_limit(PAGE_SIZE)
+                    .opt_cursor(cursor),
+            )
+            .await?;
+        bots.extend(
+            resp.members
+                .into_iter()
+                .filter(|user| user.flags.is_bot == Some(true) || user.deleted == Some(true))
+                .map(|user| user.id),
+        );
+        cursor = resp.response_metadata.and_then(|meta| meta.next_cursor);
+        if cursor.is_none() {
+            return Ok(bots);
+        }
+    }
+}
+
+/// Post `announcement` with `poster`, such as an Incoming Webhook, which only posts to one
+/// channel. `job` is the cron announcing, if it's not from a command.
+#[instrument(skip_all)]
+async fn announce_with(
+    job: Option<&CronJob>,
+    token: &SlackApiToken,
+    poster: &dyn Poster,
+    announcement: &Announcement,
+    config: &Config,
+    globals: &Globals,
+) -> Outcome {
+    let channel = WorkspaceChannel::new(token, &config.channel_id);
+    if let Err(outcome) = claim(&channel, &announcement.message, config, globals) {
+        return outcome;
+    }
+
+    let content = &announcement.content;
+    match poster.post(content).await {
+        Ok(()) => {
+            #[cfg(feature = "metrics")]
+            metrics::counter!("beerbot_messages_posted_total", "channel" => config.channel_id.to_string())
+                .increment(1);
+            globals.stats.record_post();
+            exec::on_post(config, &config.channel_id, content);
+            if let Err(e) =
+                audit::record(config, &globals.http, &config.channel_id, content, job).await
+            {
+                warn!(?e, "failed to audit message");
+            }
+            Outcome::Posted
+        }
+        Err(e) => {
+            #[cfg(feature = "metrics")]
+            metrics::counter!("beerbot_post_failures_total", "channel" => config.channel_id.to_string())
+                .increment(1);
+            warn!(?e, "failed to post");
+            release(&channel, &announcement.message, globals);
+            Outcome::Failed
+        }
+    }
+}
+
+/// How long until `dt`, or zero if it's already passed, e.g. when it's now or the clock jumped.
+fn until(dt: DateTime<Local>) -> Duration {
+    (dt - Local::now()).to_std().unwrap_or_default()
+}
+
+/// A random delay of up to `max` seconds, which never reaches the occurrence of `job` after `next`.
+fn jitter(job: &CronJob, next: DateTime<Local>, max: u64) -> Duration {
+    if max == 0 {
+        return Duration::ZERO;
+    }
+
+    let max = job
+        .next_occurrence(next)
+        .and_then(|following| (following - next).to_std().ok())
+        .map_or(max, |gap| max.min(gap.as_secs().saturating_sub(1)));
+    Duration::from_secs(rand::thread_rng().gen_range(0..=max))
+}
+
+/// Whether `dt` is within `quiet_hours`, in `job`'s timezone.
+fn is_quiet(job: &CronJob, dt: DateTime<Local>, globals: &Globals) -> bool {
+    globals
+        .cfg
+        .load()
+        .quiet_hours
+        .is_some_and(|quiet| quiet.contains(job.time_of_day(&dt)))
+}
+
+/// Load and validate the config again, keeping the current tokens as they can't change without a
+/// restart. A config given when embedding has nowhere to be reloaded from, so is kept as is.
+async fn reload_config(current: &Config, interval_start: DateTime<Local>) -> Result<Config> {
+    let Source::Loaded { path } = &current.source else {
+        bail!("config wasn't loaded by Config::new, so there's nothing to reload it from");
+    };
+    let mut new = Config::new(path.as_deref()).await?;
+    new.validate()?;
+    new.count_intervals_from(interval_start);
+
+    if new.token.token_value != current.token.token_value {
+        warn!("token changed, restart Beer Bot to use it");
+        new.token = current.token.clone();
+    }
+
+    let tokens_changed = new.workspaces.len() != current.workspaces.len()
+        || new
+            .workspaces
+            .iter()
+            .zip(&current.workspaces)
+            .any(|(new, current)| {
+                #[cfg(feature = "commands")]
+                if socket_token_changed(&new.socket_token, &current.socket_token) {
+                    return true;
+                }
+                new.token.token_value != current.token.token_value
+            });
+    if tokens_changed {
+        warn!("workspaces changed, restart Beer Bot to use them");
+        new.workspaces = current.workspaces.clone();
+    }
+
+    #[cfg(feature = "commands")]
+    if socket_token_changed(&new.socket_token, &current.socket_token) {
+        warn!("socket_token changed, restart Beer Bot to use it");
+        new.socket_token = current.socket_token.clone();
+    }
+
+    debug!(config = %new, "reloaded config");
+
+    Ok(new)
+}
+
+#[cfg(feature = "commands")]
+fn socket_token_changed(new: &Option<SlackApiToken>, current: &Option<SlackApiToken>) -> bool {
+    new.as_ref().map(|t| &t.token_value) != current.as_ref().map(|t| &t.token_value)
+}
+
+/// Schedule a message to be sent by Slack at `post_at`, unless one is already scheduled then from a
+/// previous run.
+async fn schedule_message(
+    post_at: DateTime<Local>,
+    job: &CronJob,
+    token: &SlackApiToken,
+    channel_id: &SlackChannelId,
+    globals: &Globals,
+    builder: &MessageBuilder<'_>,
+    content: &OnceCell<SlackMessageContent>,
+) -> Result<()> {
+    let config = globals.cfg.load();
+    let post_at = SlackDateTime(post_at.with_timezone(&Utc));
+    let session = globals.client.open_session(token);
+
+    let scheduled = session
+        .chat_scheduled_messages_list(
+            &SlackApiChatScheduledMessagesListRequest::new().with_channel(channel_id.clone()),
+        )
+        .await?;
+    if scheduled
+        .scheduled_messages
+        .iter()
+        .any(|m| m.post_at == post_at)
+    {
+        info!(?post_at, "message already scheduled");
+        return Ok(());
+    }
+
+    // Slack can't post the parent of a daily thread that's not started yet, so send it when due
+    let date = job.date(post_at.0.with_timezone(&Local));
+    if config.thread_mode && job.thread_ts.is_none() && date != job.today() {
+        bail!("daily thread for {} isn't started yet", date);
+    }
+    let channel = WorkspaceChannel::new(token, channel_id);
+    let thread_ts = thread(Some(job), &channel, &session, &config, globals).await;
+
+    // Built once for every channel
+    let content = content.get_or_try_init(|| builder.build_message()).await?;
+    if config.dry_run {
+        info!(
+            content = serde_json::to_string(content)?,
+            "dry run, not scheduling"
+        );
+        return Ok(());
+    }
+
+    // Mutes are as of now, as Slack can't change the message once it's scheduled
+    let content = without_muted(Some(job), channel_id, &session, content, globals).await;
+    let request = SlackApiChatScheduleMessageRequest::new(
+        channel_id.clone(),
+        content.into_owned(),
+        post_at.clone(),
+    )
+    .opt_thread_ts(thread_ts)
+    .opt_username(job.username.clone())
+    .opt_icon_emoji(job.icon_emoji.clone())
+    .opt_unfurl_links(config.unfurl())
+    .opt_unfurl_media(config.unfurl());
+    log_payload(&request);
+    session.chat_schedule_message(&request).await?;
+    debug!(?post_at, "scheduled message");
+
+    Ok(())
+}
+
+/// The thread to reply in for `job` in `channel`, either its `thread_ts` if that's still there, or
+/// today's daily thread with `thread_mode`. `None` to post to the channel.
+async fn thread(
+    job: Option<&CronJob>,
+    channel: &WorkspaceChannel,
+    session: &SlackClientSession<'_, SlackClientHyperHttpsConnector>,
+    config: &Config,
+    globals: &Globals,
+) -> Option<SlackTs> {
+    let job = job?;
+    if let Some(ts) = &job.thread_ts {
+        if topic_thread(ts, channel, session, globals).await {
+            return Some(ts.clone());
+        }
+        warn!(%ts, "thread not found, sending to channel");
+        return None;
+    }
+    if !config.thread_mode {
+        return None;
+    }
+
+    match daily_thread(job, channel, session, globals).await {
+        Ok(ts) => Some(ts),
+        Err(e) => {
+            warn!(?e, "failed to find daily thread, sending to channel");
+            None
+        }
+    }
+}
+
+/// Find today's parent message in `channel` for `thread_mode`, posting a new one if needed.
+async fn daily_thread(
+    job: &CronJob,
+    channel: &WorkspaceChannel,
+    session: &SlackClientSession<'_, SlackClientHyperHttpsConnector>,
+    globals: &Globals,
+) -> Result<SlackTs> {
+    let today = job.today();
+    let mut threads = globals.daily_threads.lock().await;
+    if let Some((date, ts)) = threads.get(channel) {
+        if *date == today {
+            return Ok(ts.clone());
+        }
+    }
+
+    let resp = post_message(
+        session,
+        &SlackApiChatPostMessageRequest::new(
+            channel.channel_id.clone(),
+            SlackMessageContent::new().with_text(DAILY_THREAD_TEXT.to_string()),
+        ),
+        globals.cfg.load().post_retries,
+    )
+    .await?;
+    debug!(ts = %resp.ts, "started daily thread");
+    threads.insert(channel.clone(), (today, resp.ts.clone()));
+
+    Ok(resp.ts)
+}
+
+/// Whether `ts` is a message in `channel` to reply to, asking Slack the first time only.
+async fn topic_thread(
+    ts: &SlackTs,
+    channel: &WorkspaceChannel,
+    session: &SlackClientSession<'_, SlackClientHyperHttpsConnector>,
+    globals: &Globals,
+) -> bool {
+    let key = (channel.clone(), ts.clone());
+    let mut threads = globals.topic_threads.lock().await;
+    if let Some(found) = threads.get(&key) {
+        return *found;
+    }
+
+    let request = SlackApiConversationsRepliesRequest::new(channel.channel_id.clone(), ts.clone())
+        .with_limit(1);
+    let found = match session.conversations_replies(&request).await {
+        Ok(_) => true,
+        // e.g. thread_not_found, once the message is deleted
+        Err(SlackClientError::ApiError(e)) => {
+            debug!(code = e.code, %ts, "thread lookup rejected");
+            false
+        }
+        Err(e) => {
+            // Not remembered, so it's looked up again next time
+            warn!(?e, %ts, "failed to look up thread, replying anyway");
+            return true;
+        }
+    };
+    debug!(%ts, found, "looked up thread");
+    threads.insert(key, found);
+
+    found
+}
+
+/// React to the posted message with each of `reactions`.
+async fn add_reactions(
+    session: &SlackClientSession<'_, SlackClientHyperHttpsConnector>,
+    posted: &SlackApiChatPostMessageResponse,
+    reactions: &[String],
+) {
+    for name in reactions {
+        // The response has the channel's ID, which reactions.add needs rather than its name
+        let request = SlackApiReactionsAddRequest::new(
+            posted.channel.clone(),
+            SlackReactionName(name.trim_matches(':').to_string()),
+            posted.ts.clone(),
+        );
+        match session.reactions_add(&request).await {
+            Ok(_) => trace!(name, ts = %posted.ts, "added reaction"),
+            Err(e) => warn!(?e, name, "failed to add reaction"),
+        }
+    }
+}
+
+/// Log the JSON sent to Slack for `request`, only serialising it if tracing, to see why blocks
+/// render as they do.
+fn log_payload(request: &impl serde::Serialize) {
+    if tracing::enabled!(Level::TRACE) {
+        match serde_json::to_string(request) {
+            Ok(payload) => trace!(payload, "sending to Slack"),
+            Err(e) => trace!(?e, "failed to serialise payload"),
+        }
+    }
+}
+
+/// Post `request`, attempting up to `attempts` times with exponential backoff between attempts.
+/// Slack API errors (e.g. `channel_not_found`) are not transient, so are returned immediately,
+/// except `not_in_channel`, where the channel is joined and the post tried once more.
+async fn post_message(
+    session: &SlackClientSession<'_, SlackClientHyperHttpsConnector>,
+    request: &SlackApiChatPostMessageRequest,
+    attempts: u32,
+) -> Result<SlackApiChatPostMessageResponse, SlackClientError> {
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 1;
+    let mut joined = false;
+    log_payload(request);
+    loop {
+        match session.chat_post_message(request).await {
+            Ok(resp) => return Ok(resp),
+            Err(SlackClientError::ApiError(e)) if e.code == "not_in_channel" && !joined => {
+                info!(channel = %request.channel, "not in channel, joining");
+                session
+                    .conversations_join(&SlackApiConversationsJoinRequest::new(
+                        request.channel.clone(),
+                    ))
+                    .await?;
+                joined = true;
+            }
+            Err(e @ SlackClientError::ApiError(_)) => return Err(e),
+            Err(e) if attempt >= attempts => return Err(e),
+            Err(e) => {
+                let delay = match &e {
+                    SlackClientError::RateLimitError(SlackRateLimitError {
+                        retry_after: Some(retry_after),
+                        ..
+                    }) => *retry_after,
+                    _ => backoff,
+                };
+                debug!(?e, attempt, ?delay, "failed to send message, retrying");
+                tokio::time::sleep(delay).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    /// A client for Slack's API on `server`, which is plain http.
+    fn client(server: &MockServer) -> SlackHyperClient {
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        let http = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .unwrap()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        SlackClient::new(
+            SlackClientHyperHttpsConnector::from(http)
+                .with_slack_api_url(&format!("{}/api", server.uri())),
+        )
+    }
+
+    fn request() -> SlackApiChatPostMessageRequest {
+        SlackApiChatPostMessageRequest::new(
+            "C0123".into(),
+            SlackMessageContent::new().with_text("Beer time!".into()),
+        )
+    }
+
+    fn posted() -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "ok": true,
+            "channel": "C0123",
+            "ts": "1712345678.123456",
+            "message": { "ts": "1712345678.123456", "text": "Beer time!" },
+        }))
+    }
+
+    #[tokio::test]
+    async fn messages_are_posted() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat.postMessage"))
+            .and(body_string_contains("C0123"))
+            .and(body_string_contains("Beer time!"))
+            .respond_with(posted())
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = client(&server);
+        let token = SlackApiToken::new("xoxb-test".into());
+        let session = client.open_session(&token);
+
+        let resp = post_message(&session, &request(), 3).await.unwrap();
+
+        assert_eq!(resp.ts, "1712345678.123456".into());
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let server = MockServer::start().await;
+        Mock::given(path("/api/chat.postMessage"))
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/api/chat.postMessage"))
+            .respond_with(posted())
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = client(&server);
+        let token = SlackApiToken::new("xoxb-test".into());
+        let session = client.open_session(&token);
+
+        assert!(post_message(&session, &request(), 3).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn api_errors_are_not_retried() {
+        let server = MockServer::start().await;
+        Mock::given(path("/api/chat.postMessage"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "ok": false, "error": "channel_not_found" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = client(&server);
+        let token = SlackApiToken::new("xoxb-test".into());
+        let session = client.open_session(&token);
+
+        let err = post_message(&session, &request(), 3).await.unwrap_err();
+
+        assert!(matches!(err, SlackClientError::ApiError(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn until_a_past_time_is_zero() {
+        assert_eq!(
+            until(Local::now() - chrono::Duration::hours(1)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn until_a_future_time_is_the_time_left() {
+        let left = until(Local::now() + chrono::Duration::hours(1));
+
+        assert!(left <= Duration::from_secs(60 * 60));
+        assert!(left > Duration::from_secs(59 * 60), "{:?}", left);
+    }
+}
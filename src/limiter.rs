@@ -0,0 +1,166 @@
+use slack_morphism::prelude::{SlackApiToken, SlackApiTokenValue};
+use slack_morphism::SlackChannelId;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A channel in the workspace a token is for, as the same channel can be given for several
+/// workspaces, e.g. `general`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorkspaceChannel {
+    pub token: SlackApiTokenValue,
+    pub channel_id: SlackChannelId,
+}
+
+impl WorkspaceChannel {
+    pub fn new(token: &SlackApiToken, channel_id: &SlackChannelId) -> WorkspaceChannel {
+        WorkspaceChannel {
+            token: token.token_value.clone(),
+            channel_id: channel_id.clone(),
+        }
+    }
+}
+
+/// Tracks when each channel was last posted to, to stop channels being flooded, and with which
+/// message, to stop crons firing together posting the same message twice.
+#[derive(Debug, Default)]
+pub struct Limiter {
+    last_posts: Mutex<HashMap<WorkspaceChannel, Instant>>,
+
+    /// When each channel was last posted to with each message, by the message's text.
+    last_messages: Mutex<HashMap<(WorkspaceChannel, String), Instant>>,
+}
+
+impl Limiter {
+    /// Claim a post to `channel`, unless it was last posted to less than `min_interval` ago.
+    pub fn try_claim(&self, channel: &WorkspaceChannel, min_interval: Duration) -> bool {
+        let now = Instant::now();
+        let mut last_posts = self.last_posts.lock().expect("Last posts poisoned");
+        if last_posts
+            .get(channel)
+            .is_some_and(|last| now.duration_since(*last) < min_interval)
+        {
+            return false;
+        }
+
+        last_posts.insert(channel.clone(), now);
+        true
+    }
+
+    /// Give back a claim on `channel` whose post wasn't sent, so it can be retried straight away.
+    /// The post before it was long enough ago to be forgotten, or the claim would have failed.
+    pub fn release(&self, channel: &WorkspaceChannel) {
+        self.last_posts
+            .lock()
+            .expect("Last posts poisoned")
+            .remove(channel);
+    }
+
+    /// Claim a post of `message` to `channel`, unless the same message was posted there less than
+    /// `window` ago. Never limited if `window` is zero.
+    pub fn try_claim_message(
+        &self,
+        channel: &WorkspaceChannel,
+        message: &str,
+        window: Duration,
+    ) -> bool {
+        if window.is_zero() {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut last_messages = self.last_messages.lock().expect("Last messages poisoned");
+        // Forget anything old enough that it can't be a duplicate, so this doesn't grow forever
+        last_messages.retain(|_, last| now.duration_since(*last) < window);
+        let key = (channel.clone(), message.to_string());
+        if last_messages.contains_key(&key) {
+            return false;
+        }
+
+        last_messages.insert(key, now);
+        true
+    }
+
+    /// Give back a claim on posting `message` to `channel` which wasn't sent.
+    pub fn release_message(&self, channel: &WorkspaceChannel, message: &str) {
+        self.last_messages
+            .lock()
+            .expect("Last messages poisoned")
+            .remove(&(channel.clone(), message.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    /// Channel `id` in the first workspace.
+    fn channel(id: &str) -> WorkspaceChannel {
+        in_workspace("xoxb-a", id)
+    }
+
+    fn in_workspace(token: &str, id: &str) -> WorkspaceChannel {
+        WorkspaceChannel::new(
+            &SlackApiToken::new(token.to_string().into()),
+            &SlackChannelId::new(id.to_string()),
+        )
+    }
+
+    #[test]
+    fn the_same_message_is_only_claimed_once() {
+        let limiter = Limiter::default();
+        assert!(limiter.try_claim_message(&channel("C1"), "Beer time!", WINDOW));
+        assert!(!limiter.try_claim_message(&channel("C1"), "Beer time!", WINDOW));
+    }
+
+    #[test]
+    fn other_messages_and_channels_are_claimed() {
+        let limiter = Limiter::default();
+        assert!(limiter.try_claim_message(&channel("C1"), "Beer time!", WINDOW));
+        assert!(limiter.try_claim_message(&channel("C1"), "Pub o'clock", WINDOW));
+        assert!(limiter.try_claim_message(&channel("C2"), "Beer time!", WINDOW));
+    }
+
+    #[test]
+    fn released_messages_are_claimed_again() {
+        let limiter = Limiter::default();
+        assert!(limiter.try_claim_message(&channel("C1"), "Beer time!", WINDOW));
+        limiter.release_message(&channel("C1"), "Beer time!");
+        assert!(limiter.try_claim_message(&channel("C1"), "Beer time!", WINDOW));
+    }
+
+    #[test]
+    fn messages_are_never_limited_without_a_window() {
+        let limiter = Limiter::default();
+        assert!(limiter.try_claim_message(&channel("C1"), "Beer time!", Duration::ZERO));
+        assert!(limiter.try_claim_message(&channel("C1"), "Beer time!", Duration::ZERO));
+    }
+
+    #[test]
+    fn released_channels_are_claimed_again() {
+        let limiter = Limiter::default();
+        assert!(limiter.try_claim(&channel("C1"), WINDOW));
+        assert!(!limiter.try_claim(&channel("C1"), WINDOW));
+        limiter.release(&channel("C1"));
+        assert!(limiter.try_claim(&channel("C1"), WINDOW));
+    }
+
+    #[test]
+    fn the_same_channel_in_other_workspaces_is_claimed() {
+        let limiter = Limiter::default();
+        assert!(limiter.try_claim(&in_workspace("xoxb-a", "general"), WINDOW));
+        assert!(limiter.try_claim(&in_workspace("xoxb-b", "general"), WINDOW));
+        assert!(limiter.try_claim_message(
+            &in_workspace("xoxb-a", "general"),
+            "Beer time!",
+            WINDOW
+        ));
+        assert!(limiter.try_claim_message(
+            &in_workspace("xoxb-b", "general"),
+            "Beer time!",
+            WINDOW
+        ));
+    }
+}
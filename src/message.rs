@@ -1,81 +1,916 @@
-use crate::config::Config;
+use crate::additions::Additions;
 #[cfg(feature = "giphy")]
-use crate::giphy::Giphy;
-use anyhow::Result;
-use rand::prelude::IteratorRandom;
-use slack_morphism::SlackMessageContent;
+use crate::config::GiphyRender;
+use crate::config::{Config, Message, MessageStrategy, Notify};
+#[cfg(feature = "giphy")]
+use crate::gif::{Gif, GifProvider, GifProviderKind};
+#[cfg(feature = "giphy")]
+use crate::giphy::{Giphy, GiphyError, Requests, ATTRIBUTION};
+#[cfg(feature = "giphy")]
+use crate::tenor::Tenor;
+use anyhow::{ensure, Context, Result};
+use chrono::{Datelike, Local, Utc, Weekday};
+use chrono_humanize::HumanTime;
+use chrono_tz::Tz;
+use rand::distributions::WeightedIndex;
+use rand::prelude::Distribution;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use slack_morphism::blocks::{
+    SlackBlock, SlackBlockMarkDownText, SlackContextBlock, SlackContextBlockElement,
+    SlackSectionBlock,
+};
+use slack_morphism::{SlackApiToken, SlackApiTokenValue, SlackMessageContent, SlackUserGroupId};
 use std::borrow::Cow;
-use tracing::info;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::{debug, error, info, trace, warn};
+
+/// Client used to fetch gifs, send audit records and post to webhooks or Discord, shared by every
+/// [`MessageBuilder`].
+#[cfg(any(
+    feature = "giphy",
+    feature = "audit",
+    feature = "webhook",
+    feature = "discord"
+))]
+pub type HttpClient = reqwest::Client;
+
+/// Nothing to fetch without gifs, auditing, webhooks or Discord.
+#[cfg(not(any(
+    feature = "giphy",
+    feature = "audit",
+    feature = "webhook",
+    feature = "discord"
+)))]
+pub type HttpClient = ();
+
+#[cfg(any(
+    feature = "giphy",
+    feature = "audit",
+    feature = "webhook",
+    feature = "discord"
+))]
+pub fn http_client() -> Result<HttpClient> {
+    reqwest::Client::builder()
+        .https_only(true)
+        .build()
+        .with_context(|| "Failed to initialise HTTPs client")
+}
+
+#[cfg(not(any(
+    feature = "giphy",
+    feature = "audit",
+    feature = "webhook",
+    feature = "discord"
+)))]
+pub fn http_client() -> Result<HttpClient> {
+    Ok(())
+}
+
+/// Requests for gifs shared by every [`MessageBuilder`] given them, so crons firing at the same time
+/// make one request per search and all wait for a rate limit to reset.
+#[cfg(feature = "giphy")]
+pub type GifRequests = Arc<Requests>;
+
+/// Nothing to share without gifs.
+#[cfg(not(feature = "giphy"))]
+pub type GifRequests = ();
+
+/// `content` with every `mention` in it replaced by `with`.
+pub fn replace_mention(
+    content: &SlackMessageContent,
+    mention: &str,
+    with: &str,
+) -> Result<SlackMessageContent> {
+    fn replace(value: &mut serde_json::Value, mention: &str, with: &str) {
+        match value {
+            serde_json::Value::String(s) => *s = s.replace(mention, with),
+            serde_json::Value::Array(values) => {
+                values.iter_mut().for_each(|v| replace(v, mention, with))
+            }
+            serde_json::Value::Object(map) => {
+                map.values_mut().for_each(|v| replace(v, mention, with))
+            }
+            _ => {}
+        }
+    }
+
+    let mut value = serde_json::to_value(content)?;
+    replace(&mut value, mention, with);
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Number of times to re-roll a message which was recently sent before giving up.
+const REROLL_ATTEMPTS: usize = 5;
+
+/// A message built to announce, with what it was built from.
+pub struct Announcement {
+    pub content: SlackMessageContent,
+
+    /// Reply saying where its gif is from, if `giphy_source_reply` is set.
+    pub source: Option<String>,
+
+    /// Text of the message picked, before placeholders are substituted or gifs and emoji added, so
+    /// the same message picked by different crons can be told apart from a different one.
+    pub message: String,
+}
+
+/// Which messages a cron has picked, so it doesn't repeat itself, and `sequential` carries on.
+#[derive(Debug, Default)]
+pub struct Picks {
+    /// Indices of the most recently sent messages, oldest first.
+    history: Mutex<VecDeque<usize>>,
+
+    /// Number of messages picked, for the `sequential` strategy.
+    sent: AtomicUsize,
+}
+
+/// [`Picks`] of each cron in each workspace, by the cron's index and the workspace's token, kept
+/// across reloads.
+#[derive(Debug, Default)]
+pub struct CronPicks(Mutex<HashMap<(usize, SlackApiTokenValue), Arc<Picks>>>);
+
+impl CronPicks {
+    /// Picks of cron `idx` announcing with `token`.
+    pub fn get(&self, idx: usize, token: &SlackApiToken) -> Arc<Picks> {
+        self.0
+            .lock()
+            .expect("Cron picks poisoned")
+            .entry((idx, token.token_value.clone()))
+            .or_default()
+            .clone()
+    }
+}
 
 pub struct MessageBuilder<'a> {
     cfg: &'a Config,
 
+    /// Messages picked, by this builder alone unless given shared picks.
+    picks: Arc<Picks>,
+
+    /// Picks messages and gif searches, seeded by `rng_seed` if set.
+    rng: Mutex<StdRng>,
+
+    notify: Notify,
+
+    /// User group to mention, as well as `notify`.
+    user_group: Option<SlackUserGroupId>,
+
+    /// Messages added with `/beer-add`, picked from along with the config's.
+    additions: Option<&'a Additions>,
+
+    /// What built the message, shown at the bottom for `debug_footer`.
+    footer: Option<String>,
+
+    /// Timezone today's weekday is in, for messages only picked on some weekdays.
+    timezone: Option<Tz>,
+
+    /// Index into the config's messages to always build, instead of picking one.
+    message: Option<usize>,
+
+    /// Client the gif providers fetch with.
+    #[cfg(feature = "giphy")]
+    http: HttpClient,
+
+    /// Gif providers to try in order, until one has a gif.
     #[cfg(feature = "giphy")]
-    gifs: Giphy<'a>,
+    gifs: Vec<(GifProviderKind, Box<dyn GifProvider + 'a>)>,
+}
+
+/// The gif providers to try in order, sharing `requests` with every other builder given them.
+#[cfg(feature = "giphy")]
+fn gif_providers<'a>(
+    cfg: &'a Config,
+    http: &HttpClient,
+    requests: &GifRequests,
+) -> Vec<(GifProviderKind, Box<dyn GifProvider + 'a>)> {
+    cfg.gif_providers()
+        .into_iter()
+        .map(|kind| {
+            let provider: Box<dyn GifProvider> = match kind {
+                GifProviderKind::Giphy => Box::new(
+                    Giphy::new(http.clone(), &cfg.giphy_token)
+                        .with_requests(requests.clone())
+                        .with_kind(cfg.giphy_kind)
+                        .with_base_url(cfg.giphy_base_url.clone())
+                        .with_rating(&cfg.giphy_rating)
+                        .with_attribution(cfg.giphy_attribution.as_deref().unwrap_or(ATTRIBUTION))
+                        .with_max_bytes(cfg.giphy_max_bytes)
+                        .with_max_dimensions(cfg.giphy_max_width, cfg.giphy_max_height)
+                        .with_cache(cfg.giphy_cache_size, cfg.giphy_cache_refresh),
+                ),
+                GifProviderKind::Tenor => Box::new(Tenor::new(http.clone(), &cfg.tenor_token)),
+            };
+            (kind, provider)
+        })
+        .collect()
 }
 
 impl<'a> MessageBuilder<'a> {
     #[cfg(not(feature = "giphy"))]
-    pub fn new(cfg: &'a Config) -> MessageBuilder<'a> {
-        MessageBuilder { cfg }
+    pub fn new(cfg: &'a Config, _: &HttpClient) -> MessageBuilder<'a> {
+        MessageBuilder {
+            cfg,
+            picks: Default::default(),
+            rng: Mutex::new(
+                cfg.rng_seed
+                    .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+            ),
+            notify: Notify::None,
+            user_group: None,
+            additions: None,
+            footer: None,
+            timezone: None,
+            message: None,
+        }
     }
 
     #[cfg(feature = "giphy")]
-    pub fn new(cfg: &'a Config) -> MessageBuilder<'a> {
+    pub fn new(cfg: &'a Config, http: &HttpClient) -> MessageBuilder<'a> {
         MessageBuilder {
             cfg,
-            gifs: Giphy::new(&cfg.giphy_token),
+            picks: Default::default(),
+            rng: Mutex::new(
+                cfg.rng_seed
+                    .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+            ),
+            notify: Notify::None,
+            user_group: None,
+            additions: None,
+            footer: None,
+            timezone: None,
+            message: None,
+            http: http.clone(),
+            gifs: gif_providers(cfg, http, &Default::default()),
         }
     }
 
+    /// Share requests for gifs with every other builder given `requests`.
+    #[cfg(feature = "giphy")]
+    pub fn with_gif_requests(mut self, requests: &GifRequests) -> MessageBuilder<'a> {
+        self.gifs = gif_providers(self.cfg, &self.http, requests);
+        self
+    }
+
+    #[cfg(not(feature = "giphy"))]
+    pub fn with_gif_requests(self, _: &GifRequests) -> MessageBuilder<'a> {
+        self
+    }
+
+    /// Share which messages have been picked with every other builder given `picks`, e.g. the
+    /// next one built for the same cron.
+    pub fn with_picks(mut self, picks: Arc<Picks>) -> MessageBuilder<'a> {
+        self.picks = picks;
+        self
+    }
+
+    /// Also pick from messages added with `/beer-add`.
+    pub fn with_additions(mut self, additions: &'a Additions) -> MessageBuilder<'a> {
+        self.additions = Some(additions);
+        self
+    }
+
+    /// Show `via <footer>` at the bottom of every message built, if given.
+    pub fn with_footer(mut self, footer: Option<String>) -> MessageBuilder<'a> {
+        self.footer = footer;
+        self
+    }
+
+    /// Mention `user_group` in every message built, if given.
+    pub fn with_user_group(mut self, user_group: Option<SlackUserGroupId>) -> MessageBuilder<'a> {
+        self.user_group = user_group;
+        self
+    }
+
+    /// Pick messages for today's weekday in `timezone`, or the local timezone if not given.
+    pub fn with_timezone(mut self, timezone: Option<Tz>) -> MessageBuilder<'a> {
+        self.timezone = timezone;
+        self
+    }
+
+    /// Always build the config's `index`th message, instead of picking one.
+    pub fn with_message(mut self, index: usize) -> MessageBuilder<'a> {
+        self.message = Some(index);
+        self
+    }
+
+    /// Mention `notify` in every message built.
+    pub fn with_notify(mut self, notify: Notify) -> MessageBuilder<'a> {
+        self.notify = notify;
+        self
+    }
+
     #[cfg(not(feature = "giphy"))]
     pub async fn build_message(&self) -> Result<SlackMessageContent> {
-        let msg = self.get_message();
+        self.build(&*self.get_message()?)
+    }
+
+    #[cfg(not(feature = "giphy"))]
+    fn build(&self, message: &Message) -> Result<SlackMessageContent> {
+        if let Some(blocks) = &message.blocks {
+            return self.build_template(message, blocks);
+        }
+
+        let mut msg = self.affix(self.decorate(self.render(&message.text)));
+        if let Some(mention) = self.mentions() {
+            msg = format!("{} {}", mention, msg);
+        }
         info!(msg, "sending");
-        Ok(SlackMessageContent::new().with_text(msg.to_string()))
+        let mut content = SlackMessageContent::new().with_text(msg.clone());
+        // Text isn't shown alongside blocks, so it needs its own block to add the button or footer
+        let extra = self
+            .button()
+            .into_iter()
+            .chain(self.footer())
+            .collect::<Vec<_>>();
+        if !extra.is_empty() {
+            content = content.with_blocks(
+                std::iter::once(SlackBlock::Section(
+                    SlackSectionBlock::new().with_text(SlackBlockMarkDownText::new(msg).into()),
+                ))
+                .chain(extra)
+                .collect(),
+            );
+        }
+        Ok(content)
+    }
+
+    /// Build a message to show only to whoever asked for it. Without gifs, there's nothing more to
+    /// show than the message itself.
+    #[cfg(not(feature = "giphy"))]
+    pub async fn build_preview(&self) -> Result<SlackMessageContent> {
+        self.build_message().await
+    }
+
+    /// Build a message to announce. Without gifs, there's never a reply saying where the gif is
+    /// from.
+    #[cfg(not(feature = "giphy"))]
+    pub async fn build_announcement(&self) -> Result<Announcement> {
+        let message = self.get_message()?;
+        Ok(Announcement {
+            content: self.build(&message)?,
+            source: None,
+            message: message.text.clone(),
+        })
     }
 
     #[cfg(feature = "giphy")]
     pub async fn build_message(&self) -> Result<SlackMessageContent> {
-        use slack_morphism::blocks::{
-            SlackBlock, SlackBlockPlainTextOnly, SlackHeaderBlock, SlackImageBlock,
-        };
-        use url::Url;
+        Ok(self.build(&*self.get_message()?).await?.0)
+    }
 
-        let search = self
-            .cfg
-            .gif_searches
-            .iter()
-            .choose(&mut rand::thread_rng())
-            .unwrap();
-        let gif = self.gifs.random(search).await?;
+    /// Build a message to announce, along with a reply saying where its gif is from if
+    /// `giphy_source_reply` is set.
+    #[cfg(feature = "giphy")]
+    pub async fn build_announcement(&self) -> Result<Announcement> {
+        let message = self.get_message()?;
+        let (content, gif) = self.build(&message).await?;
+        let source = gif
+            .filter(|_| self.cfg.giphy_source_reply)
+            .map(|(gif, search)| {
+                let page = if gif.page_url.is_empty() {
+                    gif.url
+                } else {
+                    gif.page_url
+                };
+                format!("Gif for \"{}\" from {}", search, page)
+            });
+        Ok(Announcement {
+            content,
+            source,
+            message: message.text.clone(),
+        })
+    }
 
-        info!(?gif, search, "sending");
+    /// Build a message to show only to whoever asked for it, with the gif's details appended.
+    #[cfg(feature = "giphy")]
+    pub async fn build_preview(&self) -> Result<SlackMessageContent> {
+        let (mut content, gif) = self.build(&*self.get_message()?).await?;
+        if let Some((gif, _)) = gif {
+            content
+                .blocks
+                .get_or_insert_with(Vec::new)
+                .push(SlackBlock::Section(
+                    SlackSectionBlock::new().with_text(
+                        SlackBlockMarkDownText::new(format!(
+                            "*Alt text:* {}\n*Gif:* {}",
+                            gif.alt_text, gif.url
+                        ))
+                        .into(),
+                    ),
+                ));
+        }
+        Ok(content)
+    }
 
+    /// Build a message with only a gif for `search`, with the provider's attribution.
+    #[cfg(feature = "giphy")]
+    pub async fn build_gif(&self, search: &str) -> Result<SlackMessageContent> {
+        use slack_morphism::blocks::SlackImageBlock;
+        use url::Url;
+
+        let (gif, attribution) = self.random_gif(search).await?;
+        info!(?gif, search, "sending gif");
         let alt = if gif.alt_text.is_empty() {
-            Cow::Borrowed(search)
+            search
         } else {
-            Cow::Owned(gif.alt_text)
+            &gif.alt_text
+        };
+        let image = SlackImageBlock::new(Url::parse(&gif.url)?, alt.to_string())
+            .with_title(attribution.into());
+
+        Ok(SlackMessageContent::new()
+            .with_text(format!("{} ({})", search, alt))
+            .with_blocks(vec![SlackBlock::Image(image)]))
+    }
+
+    /// Build `message`, along with the gif in it and what it was searched for, if any.
+    #[cfg(feature = "giphy")]
+    async fn build(
+        &self,
+        message: &Message,
+    ) -> Result<(SlackMessageContent, Option<(Gif, String)>)> {
+        use slack_morphism::blocks::{SlackBlockPlainTextOnly, SlackHeaderBlock, SlackImageBlock};
+        use url::Url;
+
+        if let Some(blocks) = &message.blocks {
+            return Ok((self.build_template(message, blocks)?, None));
+        }
+
+        let searches = WeightedIndex::new(self.cfg.gif_searches.iter().map(|s| s.weight))
+            .with_context(|| "No gif searches with a non-zero weight to pick from")?;
+        let search = &self.cfg.gif_searches[searches.sample(&mut *self.rng())].tag;
+        let msg = self.affix(self.decorate(self.render(&message.text)));
+
+        // Text is shown in notifications and read by screen readers, as blocks aren't
+        let mut text = msg.clone();
+        let mut sent = None;
+        let image = match self.random_gif(search).await {
+            Ok((gif, attribution)) => {
+                info!(?gif, search, "sending");
+
+                let alt = if gif.alt_text.is_empty() {
+                    Cow::Borrowed(search)
+                } else {
+                    Cow::Owned(gif.alt_text)
+                };
+                text = format!("{} ({})", msg, alt);
+
+                let image = match self.cfg.giphy_render {
+                    GiphyRender::Block => vec![SlackBlock::Image(
+                        SlackImageBlock::new(Url::parse(&gif.url)?, alt.to_string())
+                            .with_title(attribution.into()),
+                    )],
+                    // Unfurls don't show the attribution, so it needs its own block
+                    GiphyRender::Unfurl => vec![
+                        SlackBlock::Section(SlackSectionBlock::new().with_text(
+                            SlackBlockMarkDownText::new(format!("<{}|{}>", gif.url, alt)).into(),
+                        )),
+                        SlackBlock::Context(SlackContextBlock::new(vec![
+                            SlackContextBlockElement::MarkDown(SlackBlockMarkDownText::new(
+                                attribution.to_string(),
+                            )),
+                        ])),
+                    ],
+                };
+                sent = Some((
+                    Gif {
+                        url: gif.url,
+                        alt_text: alt.into_owned(),
+                        page_url: gif.page_url,
+                        width: gif.width,
+                        height: gif.height,
+                    },
+                    search.clone(),
+                ));
+                image
+            }
+            Err(e) => {
+                match e {
+                    GiphyError::RateLimited | GiphyError::NoResults(_) => {
+                        info!(%e, search, "no gif, falling back")
+                    }
+                    GiphyError::Unauthorized(_) => {
+                        error!(%e, search, "gif token rejected, falling back")
+                    }
+                    GiphyError::Http(_) | GiphyError::Deserialize(_) => {
+                        warn!(%e, search, "failed to get gif, falling back")
+                    }
+                }
+                self.cfg
+                    .fallback_gif_url
+                    .clone()
+                    .map(|url| SlackBlock::Image(SlackImageBlock::new(url, search.clone())))
+                    .into_iter()
+                    .collect()
+            }
         };
 
-        let content = SlackMessageContent::new().with_blocks(vec![
+        // Without an image, the header is all there is to show
+        let header = (self.cfg.show_header || image.is_empty()).then(|| {
             SlackBlock::Header(SlackHeaderBlock::new(SlackBlockPlainTextOnly::from(
-                self.get_message().clone(),
-            ))),
-            SlackBlock::Image(
-                SlackImageBlock::new(Url::parse(&gif.url)?, alt.into_owned())
-                    .with_title("Powered By GIPHY".into()),
-            ),
-        ]);
+                msg.clone(),
+            )))
+        });
+        let content = SlackMessageContent::new().with_text(text).with_blocks(
+            self.mention()
+                .into_iter()
+                .chain(header)
+                .chain(image)
+                .chain(self.button())
+                .chain(self.footer())
+                .collect(),
+        );
+
+        Ok((content, sent))
+    }
+
+    /// A gif for `search` from the first provider with one, along with that provider's
+    /// attribution. Fails with the last provider's error if none have one.
+    #[cfg(feature = "giphy")]
+    async fn random_gif(&self, search: &str) -> Result<(Gif, &str), GiphyError> {
+        let mut last = None;
+        for (kind, provider) in &self.gifs {
+            match provider.random(search).await {
+                Ok(gif) => {
+                    debug!(provider = ?kind, search, "found gif");
+                    return Ok((gif, provider.attribution()));
+                }
+                Err(e) => {
+                    if self.gifs.len() > 1 {
+                        warn!(provider = ?kind, %e, search, "no gif from provider, trying the next");
+                    }
+                    last = Some(e);
+                }
+            }
+        }
+        Err(last.expect("No gif providers"))
+    }
+
+    /// Build `message` from its Block Kit template, substituting placeholders in every string.
+    fn build_template(&self, message: &Message, blocks: &str) -> Result<SlackMessageContent> {
+        let mut blocks = serde_json::from_str(blocks).with_context(|| "Invalid blocks JSON")?;
+        self.render_json(&mut blocks);
+        let blocks: Vec<SlackBlock> =
+            serde_json::from_value(blocks).with_context(|| "Invalid Block Kit template")?;
+
+        let msg = self.affix(self.render(&message.text));
+        info!(msg, blocks = blocks.len(), "sending template");
+
+        let mut content = SlackMessageContent::new().with_blocks(
+            self.mention()
+                .into_iter()
+                .chain(blocks)
+                .chain(self.button())
+                .chain(self.footer())
+                .collect(),
+        );
+        if !msg.is_empty() {
+            content = content.with_text(msg);
+        }
 
         Ok(content)
     }
 
-    fn get_message(&self) -> &String {
-        self.cfg
-            .messages
-            .iter()
-            .choose(&mut rand::thread_rng())
-            .unwrap()
+    /// A block mentioning `notify` and `user_group`, if anyone. Header blocks are plain text only,
+    /// so mentions need their own block to notify anyone.
+    fn mention(&self) -> Option<SlackBlock> {
+        self.mentions().map(|mention| {
+            SlackBlock::Section(
+                SlackSectionBlock::new().with_text(SlackBlockMarkDownText::new(mention).into()),
+            )
+        })
+    }
+
+    /// Mentions of `notify` and `user_group`, if anyone.
+    fn mentions(&self) -> Option<String> {
+        let mentions = self
+            .notify
+            .mention()
+            .map(str::to_string)
+            .into_iter()
+            .chain(
+                self.user_group
+                    .as_ref()
+                    .map(|group| format!("<!subteam^{}>", group)),
+            )
+            .collect::<Vec<_>>();
+        (!mentions.is_empty()).then(|| mentions.join(" "))
+    }
+
+    /// An "I'm in" button to click to say you're coming.
+    #[cfg(feature = "interactions")]
+    fn button(&self) -> Option<SlackBlock> {
+        Some(crate::interactions::im_in_block(&Default::default()))
+    }
+
+    #[cfg(not(feature = "interactions"))]
+    #[inline]
+    fn button(&self) -> Option<SlackBlock> {
+        None
+    }
+
+    /// A small block saying what built the message, if `footer` is set.
+    fn footer(&self) -> Option<SlackBlock> {
+        self.footer.as_ref().map(|footer| {
+            SlackBlock::Context(SlackContextBlock::new(vec![
+                SlackContextBlockElement::MarkDown(SlackBlockMarkDownText::new(format!(
+                    "via {}",
+                    footer
+                ))),
+            ]))
+        })
+    }
+
+    /// Put `message_prefix` before and `message_suffix` after `msg`, with their placeholders
+    /// substituted.
+    fn affix(&self, msg: String) -> String {
+        if self.cfg.message_prefix.is_empty() && self.cfg.message_suffix.is_empty() {
+            return msg;
+        }
+
+        format!(
+            "{}{}{}",
+            self.render(&self.cfg.message_prefix),
+            msg,
+            self.render(&self.cfg.message_suffix)
+        )
+    }
+
+    /// Put a random emoji from `decoration_emoji` either side of `msg`, if there are any.
+    fn decorate(&self, msg: String) -> String {
+        let emoji = &self.cfg.decoration_emoji;
+        if emoji.is_empty() {
+            return msg;
+        }
+
+        let mut rng = self.rng();
+        format!(
+            "{} {} {}",
+            emoji[rng.gen_range(0..emoji.len())],
+            msg,
+            emoji[rng.gen_range(0..emoji.len())]
+        )
+    }
+
+    /// Substitute placeholders in every string within `value`.
+    fn render_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(s) => *s = self.render(s),
+            serde_json::Value::Array(values) => values.iter_mut().for_each(|v| self.render_json(v)),
+            serde_json::Value::Object(map) => map.values_mut().for_each(|v| self.render_json(v)),
+            _ => {}
+        }
+    }
+
+    /// Substitute the supported placeholders in `msg`, leaving unknown placeholders verbatim.
+    fn render(&self, msg: &str) -> String {
+        let now = Local::now();
+        let mut out = String::with_capacity(msg.len());
+        let mut rest = msg;
+
+        while let Some(end) = rest.find('}') {
+            // Start from the last `{` before the `}`, so a stray `{` doesn't swallow a placeholder
+            let Some(start) = rest[..end].rfind('{') else {
+                out.push_str(&rest[..=end]);
+                rest = &rest[end + 1..];
+                continue;
+            };
+            out.push_str(&rest[..start]);
+            let placeholder = &rest[start..=end];
+            match &placeholder[1..placeholder.len() - 1] {
+                "weekday" => out.push_str(&now.format("%A").to_string()),
+                "date" => out.push_str(&now.format("%Y-%m-%d").to_string()),
+                "time" => out.push_str(&now.format("%H:%M").to_string()),
+                "next_drink" => out.push_str(
+                    &self
+                        .cfg
+                        .next_drink(now)
+                        .map(|dt| HumanTime::from(dt - now).to_string())
+                        .unwrap_or_else(|| "in some time".to_string()),
+                ),
+                _ => {
+                    debug!(placeholder, "unknown placeholder");
+                    out.push_str(placeholder);
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+
+        out
+    }
+
+    fn get_message(&self) -> Result<Cow<'_, Message>> {
+        if let Some(idx) = self.message {
+            return self
+                .cfg
+                .messages
+                .get(idx)
+                .map(Cow::Borrowed)
+                .with_context(|| format!("No message {} in messages", idx));
+        }
+        let added = self
+            .additions
+            .map(|additions| {
+                additions
+                    .messages()
+                    .into_iter()
+                    .map(Message::from)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let weights = self.weights_for(self.cfg.messages.iter().chain(&added), self.weekday());
+        let idx = match self.cfg.message_strategy {
+            MessageStrategy::Random => self.pick_random(&weights)?,
+            MessageStrategy::Sequential => {
+                nth_enabled(&weights, self.picks.sent.fetch_add(1, Ordering::Relaxed))?
+            }
+            MessageStrategy::Daily => nth_enabled(
+                &weights,
+                Local::now().date_naive().num_days_from_ce() as usize,
+            )?,
+        };
+
+        // Added messages are after the config's, so the history stays valid as more are added
+        Ok(match self.cfg.messages.get(idx) {
+            Some(message) => Cow::Borrowed(message),
+            None => Cow::Owned(added[idx - self.cfg.messages.len()].clone()),
+        })
+    }
+
+    /// Today's weekday in the builder's timezone.
+    fn weekday(&self) -> Weekday {
+        match self.timezone {
+            Some(tz) => Utc::now().with_timezone(&tz).weekday(),
+            None => Local::now().weekday(),
+        }
+    }
+
+    /// Weights of `messages`, disabling those not for `weekday`, unless that disables them all.
+    fn weights_for<'m>(
+        &self,
+        messages: impl Iterator<Item = &'m Message> + Clone,
+        weekday: Weekday,
+    ) -> Vec<u32> {
+        let weights = messages
+            .clone()
+            .map(|m| {
+                if m.weekdays.is_empty() || m.weekdays.contains(&weekday) {
+                    m.weight
+                } else {
+                    0
+                }
+            })
+            .collect::<Vec<_>>();
+        if weights.iter().any(|weight| *weight > 0) {
+            return weights;
+        }
+
+        warn!(%weekday, "no messages for today, picking from every message");
+        messages.map(|m| m.weight).collect()
+    }
+
+    fn rng(&self) -> MutexGuard<'_, StdRng> {
+        self.rng.lock().expect("Message RNG poisoned")
+    }
+
+    /// Pick a message at random by `weights`, avoiding recently sent messages.
+    fn pick_random(&self, weights: &[u32]) -> Result<usize> {
+        let dist = WeightedIndex::new(weights)
+            .with_context(|| "No messages with a non-zero weight to pick from")?;
+        let mut rng = self.rng();
+        let mut history = self.picks.history.lock().expect("Message history poisoned");
+
+        let mut idx = dist.sample(&mut *rng);
+        if weights.len() > 1 {
+            for _ in 0..REROLL_ATTEMPTS {
+                if !history.contains(&idx) {
+                    break;
+                }
+                trace!(idx, "message recently sent, re-rolling");
+                idx = dist.sample(&mut *rng);
+            }
+        }
+
+        history.push_back(idx);
+        while history.len() > self.cfg.message_history {
+            history.pop_front();
+        }
+
+        Ok(idx)
+    }
+}
+
+/// Index of the `n`th message with a non-zero weight, wrapping around.
+fn nth_enabled(weights: &[u32], n: usize) -> Result<usize> {
+    let enabled = weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .map(|(idx, _)| idx)
+        .collect::<Vec<_>>();
+    ensure!(
+        !enabled.is_empty(),
+        "No messages with a non-zero weight to pick from"
+    );
+
+    Ok(enabled[n % enabled.len()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A config with `messages`, picked from in order.
+    fn config(messages: &[&str]) -> Config {
+        serde_json::from_value(serde_json::json!({
+            "token": "xoxb-test",
+            "channel_id": "C0123",
+            "crons": [],
+            "messages": messages,
+            "message_strategy": "sequential",
+            "health_addr": "127.0.0.1:0",
+            "metrics_addr": "127.0.0.1:0",
+        }))
+        .unwrap()
+    }
+
+    fn pick(cfg: &Config, picks: Option<Arc<Picks>>) -> String {
+        let http = http_client().unwrap();
+        let builder = MessageBuilder::new(cfg, &http);
+        let builder = match picks {
+            Some(picks) => builder.with_picks(picks),
+            None => builder,
+        };
+        let message = builder.get_message().unwrap().text.clone();
+        message
+    }
+
+    fn token(token: &str) -> SlackApiToken {
+        SlackApiToken::new(token.to_string().into())
+    }
+
+    #[test]
+    fn sequential_carries_on_across_builders() {
+        let cfg = config(&["One", "Two", "Three"]);
+        let picks = CronPicks::default();
+        assert_eq!(pick(&cfg, Some(picks.get(0, &token("xoxb-a")))), "One");
+        // As when the cron fires again after the config is reloaded
+        assert_eq!(pick(&cfg, Some(picks.get(0, &token("xoxb-a")))), "Two");
+        assert_eq!(pick(&cfg, Some(picks.get(0, &token("xoxb-a")))), "Three");
+    }
+
+    #[test]
+    fn each_cron_in_each_workspace_has_its_own_picks() {
+        let cfg = config(&["One", "Two", "Three"]);
+        let picks = CronPicks::default();
+        assert_eq!(pick(&cfg, Some(picks.get(0, &token("xoxb-a")))), "One");
+        assert_eq!(pick(&cfg, Some(picks.get(1, &token("xoxb-a")))), "One");
+        assert_eq!(pick(&cfg, Some(picks.get(0, &token("xoxb-b")))), "One");
+        assert_eq!(pick(&cfg, Some(picks.get(0, &token("xoxb-a")))), "Two");
+    }
+
+    #[test]
+    fn builders_without_shared_picks_start_again() {
+        let cfg = config(&["One", "Two", "Three"]);
+        let picks = Arc::new(Picks::default());
+        assert_eq!(pick(&cfg, Some(picks.clone())), "One");
+        assert_eq!(pick(&cfg, None), "One");
+        assert_eq!(pick(&cfg, Some(picks)), "Two");
+    }
+
+    /// `msg` rendered, along with today's weekday to expect in it.
+    fn render(msg: &str) -> (String, String) {
+        let cfg = config(&["One"]);
+        let http = http_client().unwrap();
+        let rendered = MessageBuilder::new(&cfg, &http).render(msg);
+        (rendered, Local::now().format("%A").to_string())
+    }
+
+    #[test]
+    fn known_placeholders_are_substituted() {
+        let (rendered, weekday) = render("Happy {weekday}!");
+        assert_eq!(rendered, format!("Happy {}!", weekday));
+    }
+
+    #[test]
+    fn unknown_placeholders_are_kept() {
+        let (rendered, _) = render("Happy {someday}, {}");
+        assert_eq!(rendered, "Happy {someday}, {}");
+    }
+
+    #[test]
+    fn nested_braces_substitute_the_innermost() {
+        let (rendered, weekday) = render("{{weekday}}");
+        assert_eq!(rendered, format!("{{{}}}", weekday));
+    }
+
+    #[test]
+    fn stray_braces_dont_swallow_placeholders() {
+        let (rendered, weekday) = render("{ oops {weekday} } {");
+        assert_eq!(rendered, format!("{{ oops {} }} {{", weekday));
     }
 }
@@ -0,0 +1,73 @@
+use anyhow::Result;
+use slack_morphism::SlackUserId;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::{debug, instrument, warn};
+
+use crate::persist;
+
+/// File users muted with `/beer-mute` are kept in, in the data dir.
+const MUTES_FILE: &str = "muted.json";
+
+/// Users who don't want to be pinged by `notify`, persisted so it survives restarts.
+#[derive(Debug)]
+pub struct Mutes {
+    users: Mutex<Vec<SlackUserId>>,
+
+    /// Held while writing, so an older list can't overwrite a newer one.
+    writing: tokio::sync::Mutex<()>,
+
+    /// Where the muted users are persisted, if there's anywhere to.
+    file: Option<PathBuf>,
+}
+
+impl Mutes {
+    /// Restore the users muted before the last run.
+    #[instrument]
+    pub async fn load() -> Mutes {
+        let file = persist::data_file(MUTES_FILE);
+        let users = persist::load_json(file.as_deref())
+            .await
+            .unwrap_or_else(|e| {
+                warn!(?e, "failed to read muted users, ignoring them");
+                Vec::new()
+            });
+        debug!(count = users.len(), ?file, "loaded muted users");
+
+        Mutes {
+            users: Mutex::new(users),
+            writing: tokio::sync::Mutex::new(()),
+            file,
+        }
+    }
+
+    pub fn users(&self) -> Vec<SlackUserId> {
+        self.users.lock().expect("Muted users poisoned").clone()
+    }
+
+    /// Mute or unmute `user`, persisting it for the next run, returning whether they were muted
+    /// before.
+    pub async fn set(&self, user: &SlackUserId, muted: bool) -> Result<bool> {
+        let _writing = self.writing.lock().await;
+        let was = {
+            let mut users = self.users.lock().expect("Muted users poisoned");
+            let was = users.contains(user);
+            if muted && !was {
+                users.push(user.clone());
+            } else if !muted {
+                users.retain(|u| u != user);
+            }
+            was
+        };
+        let Some(file) = &self.file else {
+            return Ok(was);
+        };
+        if was == muted {
+            return Ok(was);
+        }
+
+        persist::write_json(file, &self.users()).await?;
+
+        Ok(was)
+    }
+}
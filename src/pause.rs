@@ -0,0 +1,68 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{debug, instrument, warn};
+
+use crate::persist;
+
+/// File which exists while paused, in the data dir.
+const PAUSED_FILE: &str = "paused";
+
+/// Whether announcements are paused, persisted so it survives restarts.
+#[derive(Debug)]
+pub struct Pause {
+    paused: AtomicBool,
+
+    /// Where the paused state is persisted, if there's anywhere to.
+    file: Option<PathBuf>,
+}
+
+impl Pause {
+    /// Restore the paused state from the last run.
+    #[instrument]
+    pub async fn load() -> Pause {
+        let file = persist::data_file(PAUSED_FILE);
+        let paused = match &file {
+            Some(file) => tokio::fs::try_exists(file).await.unwrap_or_else(|e| {
+                warn!(?e, "failed to read paused state, resuming");
+                false
+            }),
+            None => false,
+        };
+        debug!(paused, ?file, "loaded paused state");
+
+        Pause {
+            paused: AtomicBool::new(paused),
+            file,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Pause or resume, returning whether it was paused before.
+    pub async fn set(&self, paused: bool) -> Result<bool> {
+        let was = self.paused.swap(paused, Ordering::Relaxed);
+        let Some(file) = &self.file else {
+            return Ok(was);
+        };
+
+        if paused {
+            if let Some(dir) = file.parent() {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+            }
+            tokio::fs::write(file, [])
+                .await
+                .with_context(|| format!("Failed to write {}", file.display()))?;
+        } else if tokio::fs::try_exists(file).await.unwrap_or(false) {
+            tokio::fs::remove_file(file)
+                .await
+                .with_context(|| format!("Failed to remove {}", file.display()))?;
+        }
+
+        Ok(was)
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// `name` in the data dir, if there is one.
+pub fn data_file(name: &str) -> Option<PathBuf> {
+    directories::ProjectDirs::from("com", "beerbot", "beerbot")
+        .map(|dirs| dirs.data_local_dir().join(name))
+}
+
+/// Read `file` as JSON, or the default if there's no file to read.
+pub async fn load_json<T: DeserializeOwned + Default>(file: Option<&Path>) -> Result<T> {
+    let Some(file) = file else {
+        return Ok(T::default());
+    };
+    if !tokio::fs::try_exists(file).await.unwrap_or(false) {
+        return Ok(T::default());
+    }
+
+    let content = tokio::fs::read(file)
+        .await
+        .with_context(|| format!("Failed to read {}", file.display()))?;
+    serde_json::from_slice(&content).with_context(|| format!("Failed to parse {}", file.display()))
+}
+
+/// Write `value` to `file` as JSON, creating its dir if needed.
+pub async fn write_json<T: Serialize>(file: &Path, value: &T) -> Result<()> {
+    let content = serde_json::to_vec_pretty(value)?;
+    if let Some(dir) = file.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    tokio::fs::write(file, content)
+        .await
+        .with_context(|| format!("Failed to write {}", file.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in a dir unique to `test`, which doesn't exist yet.
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("beerbot-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("nested").join("data.json")
+    }
+
+    #[tokio::test]
+    async fn missing_files_load_the_default() {
+        let file = scratch("missing");
+        assert_eq!(
+            load_json::<Vec<String>>(Some(&file)).await.unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            load_json::<Vec<String>>(None).await.unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn written_json_loads_back() {
+        let file = scratch("round-trip");
+        let value = vec!["Beer time!".to_string()];
+        write_json(&file, &value).await.unwrap();
+        assert_eq!(load_json::<Vec<String>>(Some(&file)).await.unwrap(), value);
+    }
+
+    #[tokio::test]
+    async fn invalid_json_is_an_error() {
+        let file = scratch("invalid");
+        write_json(&file, &"not a list").await.unwrap();
+        assert!(load_json::<Vec<String>>(Some(&file)).await.is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use crate::config::Config;
+#[cfg(feature = "discord")]
+use crate::config::Platform;
+#[cfg(feature = "discord")]
+use crate::discord::Discord;
+use crate::message::HttpClient;
+use crate::webhook::{self, Webhook};
+use anyhow::Result;
+use async_trait::async_trait;
+use slack_morphism::{SlackApiToken, SlackMessageContent};
+
+/// Somewhere to announce other than with a Slack token, which can only post.
+#[async_trait]
+pub trait Poster: Send + Sync {
+    async fn post(&self, content: &SlackMessageContent) -> Result<()>;
+}
+
+/// Where to announce instead of with `token`: Discord if it's the `platform`, otherwise the
+/// Incoming Webhook if there's no token.
+pub fn poster<'a>(
+    cfg: &'a Config,
+    http: &'a HttpClient,
+    token: &SlackApiToken,
+) -> Option<Box<dyn Poster + 'a>> {
+    #[cfg(feature = "discord")]
+    if cfg.platform == Platform::Discord {
+        return cfg
+            .discord_webhook_url
+            .as_ref()
+            .map(|url| Box::new(Discord::new(http, url)) as Box<dyn Poster>);
+    }
+
+    webhook::url(cfg, token).map(|url| Box::new(Webhook::new(http, url)) as Box<dyn Poster>)
+}
+
+/// Whether `token` is used to announce, rather than a [`Poster`].
+pub fn uses_token(cfg: &Config, token: &SlackApiToken) -> bool {
+    #[cfg(feature = "discord")]
+    if cfg.platform == Platform::Discord {
+        return false;
+    }
+
+    webhook::url(cfg, token).is_none()
+}
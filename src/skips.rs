@@ -0,0 +1,93 @@
+use chrono::{DateTime, Local, TimeDelta};
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// How long a skip is remembered after the announcement was due, long enough for any workspace's
+/// copy of the cron to have seen it, even delayed by `jitter_seconds`.
+const FORGET_AFTER: TimeDelta = TimeDelta::days(1);
+
+/// Announcements skipped with `/skip-next`, by the index of the cron and when it's due, so every
+/// workspace and channel skips the same announcement.
+#[derive(Debug, Default)]
+pub struct Skips(Mutex<HashSet<(usize, DateTime<Local>)>>);
+
+impl Skips {
+    /// Skip the soonest of `upcoming` not already skipped, returning it. `upcoming` is every cron's
+    /// announcements after `now`, by cron index, and must include at least one more per cron than
+    /// are skipped.
+    pub fn skip_next(
+        &self,
+        now: DateTime<Local>,
+        upcoming: impl IntoIterator<Item = (usize, DateTime<Local>)>,
+    ) -> Option<(usize, DateTime<Local>)> {
+        let mut skips = self.0.lock().expect("Skips poisoned");
+        skips.retain(|(_, at)| *at + FORGET_AFTER > now);
+        let mut upcoming = upcoming
+            .into_iter()
+            .filter(|skip| !skips.contains(skip))
+            .collect::<Vec<_>>();
+        upcoming.sort_by_key(|(_, at)| *at);
+        let next = upcoming.first().copied()?;
+        skips.insert(next);
+        Some(next)
+    }
+
+    /// How many announcements are skipped, including any already due.
+    pub fn count(&self) -> usize {
+        self.0.lock().expect("Skips poisoned").len()
+    }
+
+    /// Whether cron `idx`'s announcement due `at` is skipped.
+    pub fn is_skipped(&self, idx: usize, at: DateTime<Local>) -> bool {
+        self.0.lock().expect("Skips poisoned").contains(&(idx, at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: i64) -> DateTime<Local> {
+        DateTime::UNIX_EPOCH.with_timezone(&Local) + TimeDelta::hours(hour)
+    }
+
+    #[test]
+    fn skips_the_soonest_announcement_of_any_cron() {
+        let skips = Skips::default();
+        let upcoming = [(0, at(17)), (1, at(12)), (0, at(41))];
+
+        assert_eq!(skips.skip_next(at(0), upcoming), Some((1, at(12))));
+        assert!(skips.is_skipped(1, at(12)));
+        assert!(!skips.is_skipped(0, at(17)));
+    }
+
+    #[test]
+    fn skipping_again_skips_the_one_after() {
+        let skips = Skips::default();
+        let upcoming = [(0, at(17)), (0, at(41))];
+
+        assert_eq!(skips.skip_next(at(0), upcoming), Some((0, at(17))));
+        assert_eq!(skips.skip_next(at(0), upcoming), Some((0, at(41))));
+        assert_eq!(skips.skip_next(at(0), upcoming), None);
+    }
+
+    #[test]
+    fn checking_a_skip_doesnt_use_it_up() {
+        let skips = Skips::default();
+        skips.skip_next(at(0), [(0, at(17))]);
+
+        // As each workspace's copy of the cron does
+        assert!(skips.is_skipped(0, at(17)));
+        assert!(skips.is_skipped(0, at(17)));
+    }
+
+    #[test]
+    fn old_skips_are_forgotten() {
+        let skips = Skips::default();
+        skips.skip_next(at(0), [(0, at(17))]);
+        skips.skip_next(at(17 + 24), [(0, at(17 + 48))]);
+
+        assert!(!skips.is_skipped(0, at(17)));
+        assert_eq!(skips.count(), 1);
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::Result;
+use async_scoped::spawner::use_tokio::Tokio;
+use async_scoped::{Scope, TokioScope};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, instrument, warn};
+
+use crate::persist;
+
+/// Default file stats are kept in, in the data dir.
+const STATS_FILE: &str = "stats.json";
+
+/// How often stats are written to disk, if they've changed.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Counts {
+    /// Announcements posted.
+    pub total: u64,
+
+    pub first_post: Option<DateTime<Utc>>,
+}
+
+/// Counts of announcements posted, persisted so they survive restarts.
+#[derive(Debug)]
+pub struct Stats {
+    counts: Mutex<Counts>,
+
+    /// Whether the counts have changed since they were last written.
+    dirty: AtomicBool,
+
+    file: Option<PathBuf>,
+}
+
+impl Stats {
+    /// Load the stats from `file`, or the data dir if not given.
+    #[instrument]
+    pub async fn load(file: Option<PathBuf>) -> Stats {
+        let file = file.or_else(|| persist::data_file(STATS_FILE));
+        let counts = persist::load_json(file.as_deref())
+            .await
+            .unwrap_or_else(|e| {
+                warn!(?e, "failed to read stats, starting again");
+                Counts::default()
+            });
+        debug!(?counts, ?file, "loaded stats");
+
+        Stats {
+            counts: Mutex::new(counts),
+            dirty: AtomicBool::new(false),
+            file,
+        }
+    }
+
+    pub fn counts(&self) -> Counts {
+        self.counts.lock().expect("Stats poisoned").clone()
+    }
+
+    /// Count an announcement being posted.
+    pub fn record_post(&self) {
+        let mut counts = self.counts.lock().expect("Stats poisoned");
+        counts.total += 1;
+        counts.first_post.get_or_insert_with(Utc::now);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Write the stats to disk, if they've changed.
+    pub async fn flush(&self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let res = self.write(file).await;
+        if res.is_err() {
+            // Try again next time
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        res
+    }
+
+    async fn write(&self, file: &Path) -> Result<()> {
+        persist::write_json(file, &self.counts()).await?;
+        debug!(?file, "flushed stats");
+
+        Ok(())
+    }
+}
+
+/// Periodically write `stats` to disk.
+pub fn init<'a>(stats: Arc<Stats>) -> [(Scope<'a, (), Tokio>, ()); 1] {
+    [unsafe {
+        TokioScope::scope(move |s: &mut Scope<'_, (), Tokio>| {
+            s.spawn_cancellable(
+                async move {
+                    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+                    loop {
+                        interval.tick().await;
+                        if let Err(e) = stats.flush().await {
+                            warn!(?e, "failed to write stats");
+                        }
+                    }
+                },
+                || (),
+            )
+        })
+    }]
+}
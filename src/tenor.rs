@@ -0,0 +1,129 @@
+use crate::gif::{Gif, GifProvider};
+use crate::giphy::{self, GiphyError};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use url::Url;
+use url_macro::url;
+
+pub struct Tenor<'a> {
+    client: Client,
+    token: &'a str,
+    search_url: Url,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    results: Vec<GifResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GifResponse {
+    content_description: String,
+
+    #[serde(default)]
+    itemurl: String,
+    media_formats: MediaFormats,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaFormats {
+    gif: MediaObject,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaObject {
+    url: String,
+
+    /// Width and height in pixels.
+    #[serde(default)]
+    dims: Vec<u32>,
+}
+
+impl<'a> Tenor<'a> {
+    pub fn new(client: Client, tenor_token: &'a str) -> Tenor<'a> {
+        Tenor {
+            client,
+            token: tenor_token,
+            search_url: url!("https://tenor.googleapis.com/v2/search"),
+        }
+    }
+}
+
+#[async_trait]
+impl GifProvider for Tenor<'_> {
+    async fn random(&self, search: &str) -> Result<Gif, GiphyError> {
+        let request = self.client.get(self.search_url.clone()).query(&[
+            ("key", self.token),
+            ("q", search),
+            ("random", "true"),
+            ("limit", "1"),
+            ("media_filter", "gif"),
+            ("contentfilter", "medium"),
+        ]);
+        first_gif(giphy::send::<Response>(request).await?, search)
+    }
+
+    fn attribution(&self) -> &str {
+        "Via Tenor"
+    }
+}
+
+/// The first gif in `response`, as long as it has a URL to show it from, which Tenor doesn't
+/// always send.
+fn first_gif(response: Response, search: &str) -> Result<Gif, GiphyError> {
+    response
+        .results
+        .into_iter()
+        .next()
+        .map(Gif::from)
+        .filter(|gif| Url::parse(&gif.url).is_ok())
+        .ok_or_else(|| GiphyError::NoResults(search.to_string()))
+}
+
+impl From<GifResponse> for Gif {
+    fn from(value: GifResponse) -> Self {
+        Gif {
+            url: value.media_formats.gif.url.clone(),
+            alt_text: value.content_description,
+            page_url: value.itemurl,
+            width: value.media_formats.gif.dims.first().copied(),
+            height: value.media_formats.gif.dims.get(1).copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(url: &str) -> Response {
+        serde_json::from_value(serde_json::json!({
+            "results": [{
+                "content_description": "Cheers",
+                "media_formats": { "gif": { "url": url } },
+            }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn gif_with_a_url_is_found() {
+        let gif = first_gif(response("https://media.tenor.com/beer.gif"), "beer").unwrap();
+
+        assert_eq!(gif.url, "https://media.tenor.com/beer.gif");
+    }
+
+    #[test]
+    fn gif_without_a_url_is_no_results() {
+        for url in ["", "not a url"] {
+            let res = first_gif(response(url), "beer");
+
+            assert!(
+                matches!(&res, Err(GiphyError::NoResults(search)) if search == "beer"),
+                "{:?}",
+                res
+            );
+        }
+    }
+}
@@ -0,0 +1,62 @@
+#![cfg_attr(not(feature = "webhook"), allow(unused_imports))]
+use crate::config::Config;
+use crate::message::HttpClient;
+use crate::post::Poster;
+use anyhow::Result;
+use async_trait::async_trait;
+use slack_morphism::{SlackApiToken, SlackMessageContent};
+use tracing::{instrument, trace};
+use url::Url;
+
+/// The Incoming Webhook to post to instead of using `token`, if there's no token to post with.
+#[cfg(feature = "webhook")]
+pub fn url<'a>(cfg: &'a Config, token: &SlackApiToken) -> Option<&'a Url> {
+    cfg.webhook_url
+        .as_ref()
+        .filter(|_| token.token_value.0.is_empty())
+}
+
+#[cfg(not(feature = "webhook"))]
+#[inline]
+pub fn url<'a>(_: &'a Config, _: &SlackApiToken) -> Option<&'a Url> {
+    None
+}
+
+/// Posts to an Incoming Webhook, which only posts to the channel it was made for.
+pub struct Webhook<'a> {
+    http: &'a HttpClient,
+    url: &'a Url,
+}
+
+impl<'a> Webhook<'a> {
+    pub fn new(http: &'a HttpClient, url: &'a Url) -> Webhook<'a> {
+        Webhook { http, url }
+    }
+}
+
+#[async_trait]
+impl Poster for Webhook<'_> {
+    async fn post(&self, content: &SlackMessageContent) -> Result<()> {
+        post(self.http, self.url, content).await
+    }
+}
+
+/// Post `content` to the Incoming Webhook at `url`.
+#[cfg(feature = "webhook")]
+#[instrument(skip_all)]
+pub async fn post(http: &HttpClient, url: &Url, content: &SlackMessageContent) -> Result<()> {
+    trace!(?content, "posting to webhook");
+    http.post(url.clone())
+        .json(content)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "webhook"))]
+#[inline]
+pub async fn post(_: &HttpClient, _: &Url, _: &SlackMessageContent) -> Result<()> {
+    Ok(())
+}
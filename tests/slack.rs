@@ -0,0 +1,119 @@
+//! Runs beer-bot against a mock Slack and Giphy, so it needs no network or tokens.
+#![cfg(feature = "giphy")]
+
+use std::time::Duration;
+
+use beer_bot::config::Config;
+use serde_json::json;
+use slack_morphism::prelude::SlackClientHyperHttpsConnector;
+use wiremock::matchers::{body_string_contains, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Longest to wait for an announcement, well past the cron firing.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Gif Giphy answers every search with.
+const GIF_URL: &str = "https://media.giphy.com/media/beer/giphy.webp";
+
+/// A config announcing every second to `C0123`, with gifs from `server`.
+fn config(server: &MockServer) -> Config {
+    let stats = std::env::temp_dir().join(format!("beerbot-slack-{}", std::process::id()));
+    serde_json::from_value(json!({
+        "token": "xoxb-test",
+        "channel_id": "C0123",
+        "crons": ["@every 1s"],
+        "messages": ["Beer time!"],
+        "gif_searches": ["beer"],
+        "giphy_token": "test",
+        "giphy_base_url": format!("{}/v1/", server.uri()),
+        "stats_path": stats.join("stats.json"),
+        "health_addr": "127.0.0.1:0",
+        "metrics_addr": "127.0.0.1:0",
+    }))
+    .unwrap()
+}
+
+/// Connector for Slack's API on `server`, which is plain http.
+fn connector(server: &MockServer) -> SlackClientHyperHttpsConnector {
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .unwrap()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    SlackClientHyperHttpsConnector::from(https).with_slack_api_url(&format!("{}/api", server.uri()))
+}
+
+/// Wait until `server` is asked to post a message.
+async fn posted(server: &MockServer) -> String {
+    loop {
+        let posts = server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|request| request.url.path() == "/api/chat.postMessage")
+            .collect::<Vec<_>>();
+        if let Some(post) = posts.first() {
+            return String::from_utf8_lossy(&post.body).into_owned();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn crons_post_to_slack() {
+    // Keep paused state, mutes and added messages away from the real data dir
+    let data = std::env::temp_dir().join(format!("beerbot-data-{}", std::process::id()));
+    std::env::set_var("XDG_DATA_HOME", &data);
+
+    let server = MockServer::start().await;
+    Mock::given(path("/api/auth.test"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "ok": true,
+            "user_id": "U0BEER",
+            "team_id": "T0123",
+            "team": "Beer",
+            "user": "beerbot",
+            "url": "https://beer.slack.com/",
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/chat.postMessage"))
+        .and(body_string_contains("C0123"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "ok": true,
+            "channel": "C0123",
+            "ts": "1712345678.123456",
+            "message": { "ts": "1712345678.123456", "text": "Beer time!" },
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/v1/gifs/random"))
+        .and(query_param("tag", "beer"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "data": {
+                "alt_text": "Cheers",
+                "url": "https://giphy.com/gifs/beer",
+                "images": { "original": { "webp": GIF_URL } },
+            },
+        })))
+        .expect(1..)
+        .mount(&server)
+        .await;
+
+    // Giphy is plain http too, so it needs a client which isn't https only
+    let run = beer_bot::run_with(config(&server), connector(&server), reqwest::Client::new());
+    let body = tokio::select! {
+        res = run => {
+            panic!("stopped before announcing: {:?}", res)
+        }
+        body = posted(&server) => body,
+        _ = tokio::time::sleep(TIMEOUT) => panic!("nothing announced in {:?}", TIMEOUT),
+    };
+    assert!(body.contains("Beer time!"), "{}", body);
+    assert!(body.contains(GIF_URL), "{}", body);
+}